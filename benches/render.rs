@@ -0,0 +1,113 @@
+use std::{
+    collections::VecDeque,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use pilfer::{
+    buffer::{Buffer, Tab},
+    input::Input,
+    instance::Instance,
+    keys::KeyBindings,
+    models::{AppContext, Attributes, GatewayStats, PilferMessage},
+    preview::Previews,
+    ratelimit::RateLimiter,
+    store::MessageStore,
+    theme::Theme,
+    ui::{ui, ScreenLayout},
+};
+use reqwest::Client;
+use serde_json::json;
+use todel::models::{InstanceInfo, Message};
+use tokio::sync::mpsc;
+use tui::{backend::TestBackend, Terminal};
+
+fn app_with_messages(count: usize) -> AppContext {
+    let mut messages = MessageStore::new(count, None).unwrap();
+    for i in 0..count {
+        messages
+            .push(
+                PilferMessage::Eludris(Message {
+                    author: "bench".to_string(),
+                    content: format!(
+                        "message number {} which is long enough to wrap, see https://eludris.gay/{}",
+                        i, i
+                    ),
+                }),
+                Attributes::default(),
+            )
+            .unwrap();
+    }
+    let (events, _) = mpsc::unbounded_channel();
+    let info: InstanceInfo = serde_json::from_value(json!({
+        "instance_name": "bench",
+        "description": null,
+        "version": "0.3.3",
+        "message_limit": 2048,
+        "oprish_url": "https://example.com",
+        "pandemonium_url": "wss://example.com",
+        "effis_url": "https://example.com",
+        "file_size": 20_000_000,
+        "attachment_file_size": 25_000_000,
+    }))
+    .unwrap();
+    let instance = Instance {
+        index: 0,
+        info,
+        rest_url: String::new(),
+        name: "bench".to_string(),
+        highlights: vec![],
+        buffer: Buffer::new(messages),
+        gateway: GatewayStats::default(),
+        outbox: VecDeque::new(),
+        next_id: 0,
+        sending: false,
+        echoes: VecDeque::new(),
+        upload: None,
+        rate_limiter: RateLimiter::default(),
+        http_client: Client::new(),
+        events,
+    };
+    AppContext {
+        input: Input::default(),
+        name: "bench".to_string(),
+        instances: vec![instance],
+        current: 0,
+        tab: Tab::Instance(0),
+        mentions: Buffer::new(MessageStore::new(count, None).unwrap()),
+        search: Buffer::new(MessageStore::new(count, None).unwrap()),
+        search_query: None,
+        system: Buffer::new(MessageStore::new(count, None).unwrap()),
+        notice: None,
+        theme: Theme::default(),
+        hyperlinks: true,
+        previews: Previews::new(None),
+        selecting: false,
+        warn_before_send: false,
+        keys: KeyBindings::default(),
+        mouse: true,
+        screen: ScreenLayout::default(),
+        show_debug: false,
+        popup: None,
+        focused: Arc::new(AtomicBool::new(true)),
+        #[cfg(target_os = "linux")]
+        notification: Arc::new(std::sync::Mutex::new(None)),
+    }
+}
+
+fn render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    for count in [1_000, 10_000, 100_000] {
+        let mut app = app_with_messages(count);
+        let mut terminal = Terminal::new(TestBackend::new(80, 40)).unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(count), &count, |b, _| {
+            b.iter(|| {
+                terminal.draw(|f| ui(f, &mut app)).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
@@ -0,0 +1,196 @@
+use std::io;
+
+use crate::{
+    models::{Attributes, PilferMessage},
+    store::{MessageStore, Paged},
+};
+
+/// How many messages are loaded from the log at once when scrolling past the scrollback
+pub const PAGE_SIZE: usize = 100;
+
+/// A tab in the tab bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tab {
+    /// The chat of the instance at this index
+    Instance(usize),
+    /// Messages mentioning the user, from every instance
+    Mentions,
+    /// Results of the last `/search`
+    Search,
+    /// System messages from every instance
+    System,
+}
+
+/// A list of messages shown in its own tab
+#[derive(Debug)]
+pub struct Buffer {
+    /// The buffer's messages
+    pub messages: MessageStore,
+    /// How many of the newest messages are scrolled out of view
+    pub scroll: usize,
+    /// The selected message, counted back from the newest one like `scroll`
+    pub selected: Option<usize>,
+    /// How many messages arrived while the buffer wasn't shown
+    pub unread: usize,
+    /// How many of the unread messages mention the user
+    pub mentions: usize,
+}
+
+impl Buffer {
+    pub fn new(messages: MessageStore) -> Self {
+        Self {
+            messages,
+            scroll: 0,
+            selected: None,
+            unread: 0,
+            mentions: 0,
+        }
+    }
+
+    /// Add a new message, failing if the buffer's log couldn't be written to.
+    pub fn push(&mut self, msg: PilferMessage, attributes: Attributes) -> io::Result<()> {
+        // Keep the view anchored in place while scrolled up, messages past a gap in the paged ones
+        // don't move as the message pushed out of memory goes into the gap
+        let gap = self.messages.gap().unwrap_or(usize::MAX);
+        if self.scroll > 0 && self.scroll < gap {
+            self.scroll += 1;
+        }
+        if let Some(selected) = self.selected.as_mut().filter(|selected| **selected < gap) {
+            *selected += 1;
+        }
+        self.messages.push(msg, attributes)
+    }
+
+    /// Count a new message which arrived while the buffer isn't shown.
+    pub fn mark_unread(&mut self, mention: bool) {
+        self.unread += 1;
+        if mention {
+            self.mentions += 1;
+        }
+    }
+
+    pub fn mark_read(&mut self) {
+        self.unread = 0;
+        self.mentions = 0;
+    }
+
+    /// Remove the newest in-memory message matching `predicate`, keeping the view on the same
+    /// messages.
+    pub fn remove(&mut self, predicate: impl Fn(&PilferMessage) -> bool) -> Option<PilferMessage> {
+        let (index, message) = self.messages.remove(predicate)?;
+        if self.selected == Some(index) {
+            self.selected = None;
+        }
+        self.shift_view(index, -1);
+        self.scroll = self.scroll.min(self.messages.len().saturating_sub(1));
+        Some(message)
+    }
+
+    /// Remove every message from view.
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.scroll = 0;
+        self.selected = None;
+    }
+
+    /// Scroll towards older messages, failing if they couldn't be paged in from the log.
+    pub fn scroll_up(&mut self, amount: usize) -> io::Result<()> {
+        self.scroll += amount;
+        if self.scroll >= self.messages.len() {
+            // Past the in-memory scrollback, try to page older messages back in from the log
+            let paged = self.messages.page_in(PAGE_SIZE);
+            // The dropped messages were the newest paged ones, where the gap is now
+            if let (Ok(Paged { dropped, .. }), Some(gap)) = (&paged, self.messages.gap()) {
+                self.shift_view(gap, -(*dropped as isize));
+            }
+            self.scroll = self.scroll.min(self.messages.len().saturating_sub(1));
+            paged?;
+        }
+        Ok(())
+    }
+
+    /// Scroll towards newer messages, failing if the ones between the paged and the in-memory
+    /// messages couldn't be paged back in from the log.
+    pub fn scroll_down(&mut self, amount: usize) -> io::Result<()> {
+        self.scroll = self.scroll.saturating_sub(amount);
+        // Fill the gap before it comes into view
+        while let Some(gap) = self.messages.gap() {
+            if self.scroll >= gap + PAGE_SIZE {
+                break;
+            }
+            match self.messages.page_in_newer(PAGE_SIZE) {
+                Ok(Paged { loaded, .. }) => self.shift_view(gap, loaded as isize),
+                Err(err) => {
+                    // Jump to the in-memory messages rather than showing them next to the gap
+                    self.messages.release_paged();
+                    self.scroll = self.scroll.min(gap.saturating_sub(1));
+                    self.selected = self.selected.filter(|selected| *selected < gap);
+                    return Err(err);
+                }
+            }
+        }
+        if self.scroll == 0 {
+            self.messages.release_paged();
+        }
+        Ok(())
+    }
+
+    /// Move the view along with the messages older than `from`, counted back from the newest
+    /// message, after messages were added or removed there.
+    fn shift_view(&mut self, from: usize, amount: isize) {
+        self.scroll = shift_index(self.scroll, from, amount);
+        self.selected = self
+            .selected
+            .map(|selected| shift_index(selected, from, amount));
+    }
+}
+
+fn shift_index(index: usize, from: usize, amount: isize) -> usize {
+    if index < from {
+        index
+    } else {
+        index.saturating_add_signed(amount).max(from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Severity, SystemMessage};
+
+    fn content(buffer: &Buffer, index: usize) -> &str {
+        buffer
+            .messages
+            .iter()
+            .rev()
+            .nth(index)
+            .unwrap()
+            .message()
+            .content()
+    }
+
+    #[test]
+    fn remove_keeps_view() {
+        let mut buffer = Buffer::new(MessageStore::new(10, None).unwrap());
+        for number in 0..5 {
+            let message = SystemMessage::new(Severity::Info, None, number.to_string());
+            buffer
+                .push(PilferMessage::System(message), Attributes::default())
+                .unwrap();
+        }
+        buffer.scroll = 1;
+        buffer.selected = Some(3);
+        let removed = buffer.remove(|message| message.content() == "3");
+        assert_eq!(
+            removed.map(|message| message.content().to_string()),
+            Some("3".into())
+        );
+        assert_eq!(content(&buffer, buffer.scroll), "2");
+        assert_eq!(content(&buffer, buffer.selected.unwrap()), "1");
+
+        // Removing the selected message deselects it
+        buffer.remove(|message| message.content() == "1");
+        assert_eq!(buffer.selected, None);
+        assert_eq!(content(&buffer, buffer.scroll), "2");
+    }
+}
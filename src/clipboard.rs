@@ -0,0 +1,12 @@
+use std::io::{self, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Copy text to the system clipboard through the terminal with an OSC 52 escape sequence.
+///
+/// This works over SSH too, but the terminal has to support (and allow) it.
+pub fn copy(text: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(text))?;
+    stdout.flush()
+}
@@ -0,0 +1,62 @@
+use std::{env, fs, path::PathBuf};
+
+/// Expand a leading `~` to the user's home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), env::var_os("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            let mut expanded = PathBuf::from(home);
+            expanded.push(rest.trim_start_matches('/'));
+            expanded
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Complete a partially typed path as far as it's unambiguous.
+///
+/// Returns the completed path along with every entry it could still refer to, directories end in
+/// a `/`.
+pub fn complete_path(partial: &str) -> (String, Vec<String>) {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(index) => partial.split_at(index + 1),
+        None => ("", partial),
+    };
+    let entries = match fs::read_dir(if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_home(dir)
+    }) {
+        Ok(entries) => entries,
+        Err(_) => return (partial.to_string(), vec![]),
+    };
+    let mut candidates: Vec<String> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            // Hidden files only show up when asked for
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+            // Follow symlinks so linked directories complete like directories
+            if matches!(fs::metadata(entry.path()), Ok(meta) if meta.is_dir()) {
+                Some(format!("{}/", name))
+            } else {
+                Some(name)
+            }
+        })
+        .collect();
+    candidates.sort();
+    let completed = match candidates.split_first() {
+        Some((first, rest)) => rest.iter().fold(first.as_str(), |common, candidate| {
+            let len = common
+                .char_indices()
+                .zip(candidate.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map_or(0, |((index, a), _)| index + a.len_utf8());
+            &common[..len]
+        }),
+        None => prefix,
+    };
+    (format!("{}{}", dir, completed), candidates)
+}
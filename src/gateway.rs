@@ -1,6 +1,6 @@
 use std::{
     sync::{atomic::AtomicBool, Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use futures::{SinkExt, StreamExt};
@@ -9,21 +9,26 @@ use notify_rust::Notification;
 use notify_rust::NotificationHandle;
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use todel::models::{ClientPayload, Message, ServerPayload};
-use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::{mpsc::UnboundedSender, Mutex as AsyncMutex};
 use tokio::time;
 use tokio_tungstenite::{connect_async, tungstenite::Message as WsMessage};
-use tui::style::{Color, Style};
 
-use crate::models::{PilferMessage, SystemMessage};
+use crate::models::{AppEvent, GatewayEvent, PilferMessage, Severity, Source, SystemMessage};
+
+/// The longest time to wait between reconnection attempts, in seconds
+const MAX_RECONNECT_WAIT: u64 = 64;
+/// How long a connection has to stay up for the wait between attempts to start over
+const STABLE_CONNECTION: Duration = Duration::from_secs(60);
 
 pub async fn handle_gateway(
     gateway_url: String,
-    messages: Arc<Mutex<Vec<(PilferMessage, Style)>>>,
+    instance: usize,
+    events: UnboundedSender<(usize, AppEvent)>,
     focused: Arc<AtomicBool>,
     #[cfg(target_os = "linux")] notification: Arc<Mutex<Option<NotificationHandle>>>,
-    name: String,
 ) {
     let rng = Arc::new(AsyncMutex::new(StdRng::from_entropy()));
+    // Seconds to wait before connecting, doubled after every failed attempt
     let mut wait = 0;
     loop {
         if wait > 0 {
@@ -33,66 +38,153 @@ pub async fn handle_gateway(
         let socket = match connect_async(&gateway_url).await {
             Ok((socket, _)) => socket,
             Err(err) => {
-                if wait < 64 {
-                    wait *= 2;
-                }
-                messages.lock().unwrap().push((
-                    PilferMessage::System(SystemMessage {
-                        content: format!(
-                            "Could not connect: {:?}, reconnecting in {}s (press Ctrl+C to exit)",
-                            err, wait
-                        ),
-                    }),
-                    Style::default().fg(Color::Red),
-                ));
+                wait = back_off(wait);
+                events
+                    .send((
+                        instance,
+                        AppEvent::Message(PilferMessage::System(
+                            SystemMessage::new(
+                                Severity::Error,
+                                Some(Source::Gateway),
+                                format!(
+                                    "Could not connect, reconnecting in {}s (press Ctrl+C to exit)",
+                                    wait
+                                ),
+                            )
+                            .detail(format!("{:?}", err)),
+                        )),
+                    ))
+                    .ok();
                 continue;
             }
         };
-        wait = 0;
 
         let (mut tx, mut rx) = socket.split();
-        let ping;
-        loop {
-            if let Some(Ok(WsMessage::Text(msg))) = rx.next().await {
-                if let Ok(ServerPayload::Hello {
+        // When the last ping was sent, used to measure the gateway's latency
+        let last_ping = Arc::new(Mutex::new(None));
+        let ping = loop {
+            let msg = match rx.next().await {
+                Some(Ok(WsMessage::Text(msg))) => msg,
+                Some(Ok(_)) => continue,
+                _ => break None,
+            };
+            match decode_payload(&msg) {
+                Ok(ServerPayload::Hello {
                     heartbeat_interval, ..
-                }) = serde_json::from_str(&msg)
-                {
+                }) => {
                     // Handle ping-pong loop
                     let rng = Arc::clone(&rng);
-                    ping = tokio::spawn(async move {
+                    let last_ping = Arc::clone(&last_ping);
+                    break Some(tokio::spawn(async move {
                         time::sleep(Duration::from_secs(
                             rng.lock().await.gen_range(0..heartbeat_interval),
                         ))
                         .await;
-                        while let Ok(()) = tx
-                            .send(WsMessage::Text(
-                                serde_json::to_string(&ClientPayload::Ping).unwrap(),
-                            ))
-                            .await
-                        {
+                        loop {
+                            *last_ping.lock().unwrap() = Some(Instant::now());
+                            if tx
+                                .send(WsMessage::Text(
+                                    serde_json::to_string(&ClientPayload::Ping).unwrap(),
+                                ))
+                                .await
+                                .is_err()
+                            {
+                                break;
+                            }
                             time::sleep(Duration::from_secs(heartbeat_interval)).await;
                         }
-                    });
-                    break;
+                    }));
+                }
+                // Nothing else should be sent before the Hello payload
+                Ok(_) => {
+                    events
+                        .send((
+                            instance,
+                            AppEvent::Gateway(GatewayEvent::UnknownFrame(
+                                "Received a payload before Hello".to_string(),
+                            )),
+                        ))
+                        .ok();
+                }
+                Err(event) => {
+                    events.send((instance, AppEvent::Gateway(event))).ok();
                 }
             }
-        }
+        };
+        let ping = match ping {
+            Some(ping) => ping,
+            None => {
+                wait = back_off(wait);
+                events
+                    .send((
+                        instance,
+                        AppEvent::Message(PilferMessage::System(SystemMessage::new(
+                            Severity::Error,
+                            Some(Source::Gateway),
+                            format!("Connection closed before Hello, reconnecting in {}s", wait),
+                        ))),
+                    ))
+                    .ok();
+                continue;
+            }
+        };
+        let connected_at = Instant::now();
 
-        messages.lock().unwrap().push((
-            PilferMessage::System(SystemMessage {
-                content: "Connected to Pandemonium".to_string(),
-            }),
-            Style::default().fg(Color::Green),
-        ));
+        events
+            .send((instance, AppEvent::Gateway(GatewayEvent::Connected)))
+            .ok();
+        events
+            .send((
+                instance,
+                AppEvent::Message(PilferMessage::System(SystemMessage::new(
+                    Severity::Info,
+                    Some(Source::Gateway),
+                    "Connected to Pandemonium",
+                ))),
+            ))
+            .ok();
 
         // Handle receiving pandemonium events
+        let mut close_reason = None;
         while let Some(Ok(msg)) = rx.next().await {
             match msg {
                 WsMessage::Text(msg) => {
-                    let msg: Message = match serde_json::from_str(&msg) {
+                    let msg: Message = match decode_payload(&msg) {
                         Ok(ServerPayload::MessageCreate(msg)) => msg,
-                        _ => continue,
+                        Ok(ServerPayload::Pong) => {
+                            let latency = last_ping.lock().unwrap().take().map(|t| t.elapsed());
+                            events
+                                .send((instance, AppEvent::Gateway(GatewayEvent::Pong { latency })))
+                                .ok();
+                            continue;
+                        }
+                        Ok(ServerPayload::RateLimit { wait }) => {
+                            events
+                                .send((
+                                    instance,
+                                    AppEvent::Gateway(GatewayEvent::RateLimited {
+                                        until: Instant::now() + Duration::from_millis(wait),
+                                    }),
+                                ))
+                                .ok();
+                            continue;
+                        }
+                        // Only expected once when connecting, which is handled above
+                        Ok(ServerPayload::Hello { .. }) => {
+                            events
+                                .send((
+                                    instance,
+                                    AppEvent::Gateway(GatewayEvent::UnknownFrame(
+                                        "Received a second Hello payload".to_string(),
+                                    )),
+                                ))
+                                .ok();
+                            continue;
+                        }
+                        Err(event) => {
+                            events.send((instance, AppEvent::Gateway(event))).ok();
+                            continue;
+                        }
                     };
                     if !focused.load(std::sync::atomic::Ordering::Relaxed) {
                         #[cfg(target_os = "linux")]
@@ -106,14 +198,11 @@ pub async fn handle_gateway(
                                     notif.update()
                                 }
                                 None => {
-                                    *notif = match Notification::new()
+                                    *notif = Notification::new()
                                         .summary(&format!("New Pilfer message from {}", msg.author))
                                         .body(&msg.content)
                                         .show()
-                                    {
-                                        Ok(notif) => Some(notif),
-                                        Err(_) => None,
-                                    };
+                                        .ok();
                                 }
                             }
                         }
@@ -124,35 +213,61 @@ pub async fn handle_gateway(
                             .show()
                             .ok();
                     }
-                    // Highlight the message if your name got mentioned
-                    let style = if msg.content.to_lowercase().contains(&name.to_lowercase()) {
-                        Style::default().fg(Color::Yellow)
-                    } else {
-                        Style::default()
-                    };
                     // Add to the Pifler's context
-                    messages
-                        .lock()
-                        .unwrap()
-                        .push((PilferMessage::Eludris(msg), style));
+                    events
+                        .send((instance, AppEvent::Message(PilferMessage::Eludris(msg))))
+                        .ok();
                 }
-                WsMessage::Close(frame) => {
-                    if let Some(frame) = frame {
-                        if wait < 64 {
-                            wait *= 2;
-                        }
-                        messages.lock().unwrap().push((
-                            PilferMessage::System(SystemMessage {
-                                content: format!("{}, retrying in {}s", frame.reason, wait),
-                            }),
-                            Style::default().fg(Color::Red),
+                WsMessage::Close(Some(frame)) => close_reason = Some(frame.reason),
+                WsMessage::Binary(_) => {
+                    events
+                        .send((
+                            instance,
+                            AppEvent::Gateway(GatewayEvent::UnknownFrame(
+                                "Received a binary frame".to_string(),
+                            )),
                         ))
-                    }
-                    ping.abort();
-                    continue;
+                        .ok();
                 }
                 _ => {}
             }
         }
+        ping.abort();
+        // Connections which get dropped right away are retried as if they failed
+        if connected_at.elapsed() >= STABLE_CONNECTION {
+            wait = 0;
+        }
+        wait = back_off(wait);
+        events
+            .send((instance, AppEvent::Gateway(GatewayEvent::Disconnected)))
+            .ok();
+        let mut message = SystemMessage::new(
+            Severity::Error,
+            Some(Source::Gateway),
+            format!("Connection closed, retrying in {}s", wait),
+        );
+        if let Some(reason) = close_reason {
+            message = message.detail(reason);
+        }
+        events
+            .send((instance, AppEvent::Message(PilferMessage::System(message))))
+            .ok();
     }
 }
+
+/// The time to wait after another failed attempt to connect, in seconds.
+fn back_off(wait: u64) -> u64 {
+    (wait * 2).clamp(1, MAX_RECONNECT_WAIT)
+}
+
+/// Decode a gateway payload, returning the event to report if it can't be handled.
+fn decode_payload(msg: &str) -> Result<ServerPayload, GatewayEvent> {
+    serde_json::from_str(msg).map_err(|err| {
+        // Valid JSON that doesn't match any payload is most likely something newer than us
+        if serde_json::from_str::<serde_json::Value>(msg).is_ok() {
+            GatewayEvent::UnknownFrame(err.to_string())
+        } else {
+            GatewayEvent::UndecodableFrame(err.to_string())
+        }
+    })
+}
@@ -0,0 +1,89 @@
+use reqwest::Client;
+use todel::models::{InstanceInfo, RateLimitConf};
+
+/// Fetch an instance's info, including its rate limit configuration.
+pub async fn fetch_info(http_client: &Client, rest_url: &str) -> anyhow::Result<InstanceInfo> {
+    let res = match http_client
+        .get(format!("{}/?rate_limits", rest_url.trim_end_matches('/')))
+        .send()
+        .await
+    {
+        Ok(res) => res,
+        Err(err) => anyhow::bail!("Can not connect to Oprish: {}", err),
+    };
+    match res.json().await {
+        Ok(info) => Ok(info),
+        Err(err) => anyhow::bail!("Server returned a malformed info response: {}", err),
+    }
+}
+
+/// Describe everything an instance reports about itself, one line at a time.
+pub fn describe_info(info: &InstanceInfo) -> Vec<String> {
+    let mut lines = vec![
+        format!("Name: {}", info.instance_name),
+        format!(
+            "Description: {}",
+            info.description.as_deref().unwrap_or("-")
+        ),
+        format!("Version: {}", info.version),
+        format!("Oprish URL: {}", info.oprish_url),
+        format!("Pandemonium URL: {}", info.pandemonium_url),
+        format!("Effis URL: {}", info.effis_url),
+        format!("Message limit: {}", info.message_limit),
+        format!("File size limit: {}", format_size(info.file_size)),
+        format!(
+            "Attachment size limit: {}",
+            format_size(info.attachment_file_size)
+        ),
+    ];
+    match &info.rate_limits {
+        Some(limits) => {
+            lines.push("Rate limits:".to_string());
+            lines.push(rate_limit("Oprish info", &limits.oprish.info));
+            lines.push(rate_limit(
+                "Oprish message create",
+                &limits.oprish.message_create,
+            ));
+            lines.push(rate_limit("Oprish rate limits", &limits.oprish.rate_limits));
+            lines.push(rate_limit("Pandemonium", &limits.pandemonium));
+            for (name, conf) in [
+                ("Effis assets", &limits.effis.assets),
+                ("Effis attachments", &limits.effis.attachments),
+            ] {
+                lines.push(format!(
+                    "  {}: {} requests per {}s, {} per file",
+                    name,
+                    conf.limit,
+                    conf.reset_after,
+                    format_size(conf.file_size_limit)
+                ));
+            }
+            lines.push(rate_limit("Effis fetch file", &limits.effis.fetch_file));
+        }
+        None => lines.push("Rate limits: not provided".to_string()),
+    }
+    lines
+}
+
+fn rate_limit(name: &str, conf: &RateLimitConf) -> String {
+    format!(
+        "  {}: {} requests per {}s",
+        name, conf.limit, conf.reset_after
+    )
+}
+
+/// Format a size in bytes in a human readable way.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,120 @@
+use std::mem;
+
+use crate::split::split_message;
+
+/// The message being written along with where the cursor is in it
+#[derive(Debug, Default)]
+pub struct Input {
+    text: String,
+    /// Byte offset of the cursor, always on a character boundary
+    cursor: usize,
+    /// How many messages the text gets split into, along with the limit it was split at
+    parts: Option<(usize, usize)>,
+}
+
+impl Input {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// How many messages the text gets split into at `limit`, only worked out again once it
+    /// changes.
+    pub fn parts(&mut self, limit: usize) -> usize {
+        match self.parts {
+            Some((parts_limit, parts)) if parts_limit == limit => parts,
+            _ => {
+                let parts = split_message(&self.text, limit).len();
+                self.parts = Some((limit, parts));
+                parts
+            }
+        }
+    }
+
+    /// Replace the text, moving the cursor to its end.
+    pub fn set(&mut self, text: String) {
+        self.parts = None;
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    /// Take the text out, leaving the input empty.
+    pub fn take(&mut self) -> String {
+        self.parts = None;
+        self.cursor = 0;
+        mem::take(&mut self.text)
+    }
+
+    pub fn clear(&mut self) {
+        self.take();
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.parts = None;
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    pub fn insert_str(&mut self, text: &str) {
+        self.parts = None;
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Remove the character before the cursor.
+    pub fn backspace(&mut self) {
+        if let Some(c) = self.text[..self.cursor].chars().next_back() {
+            self.parts = None;
+            self.cursor -= c.len_utf8();
+            self.text.remove(self.cursor);
+        }
+    }
+
+    /// Remove the character under the cursor.
+    pub fn delete(&mut self) {
+        if self.cursor < self.text.len() {
+            self.parts = None;
+            self.text.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        if let Some(c) = self.text[..self.cursor].chars().next_back() {
+            self.cursor -= c.len_utf8();
+        }
+    }
+
+    pub fn right(&mut self) {
+        if let Some(c) = self.text[self.cursor..].chars().next() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Move the cursor to `offset`, or the character it's in the middle of.
+    pub fn set_cursor(&mut self, offset: usize) {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        self.cursor = offset;
+    }
+}
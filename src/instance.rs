@@ -0,0 +1,523 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use reqwest::{Client, RequestBuilder};
+use serde_json::json;
+use todel::models::{ErrorResponse, InstanceInfo, Message};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    buffer::Buffer,
+    info::format_size,
+    models::{
+        AppEvent, Attributes, GatewayEvent, GatewayStats, MessageResponse, OutgoingMessage,
+        OutgoingState, PendingEcho, PilferMessage, QueuedMessage, SendResult, Severity, Source,
+        SystemMessage, Upload, UploadEvent,
+    },
+    ratelimit::RateLimiter,
+    split::split_message,
+    upload::{file_url, upload_file},
+};
+
+/// How long to wait for a sent message to come back through the gateway
+pub const ECHO_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for Oprish to respond to a message before giving up on it
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A connection to a single Eludris instance
+pub struct Instance {
+    /// The instance's position in the instance list, events are tagged with it
+    pub index: usize,
+    /// Information about the instance
+    pub info: InstanceInfo,
+    /// Oprish URL
+    pub rest_url: String,
+    /// User name
+    pub name: String,
+    /// Words which highlight messages containing them
+    pub highlights: Vec<String>,
+    /// The instance's chat
+    pub buffer: Buffer,
+    /// The state of the gateway connection
+    pub gateway: GatewayStats,
+    /// Messages waiting to be sent, oldest first
+    pub outbox: VecDeque<QueuedMessage>,
+    /// The id the next outgoing message gets
+    pub next_id: u64,
+    /// Whether a message is currently being sent
+    pub sending: bool,
+    /// Sent messages which didn't come back through the gateway yet, oldest first
+    pub echoes: VecDeque<PendingEcho>,
+    /// Oprish's message rate limit
+    pub rate_limiter: RateLimiter,
+    /// The file currently being uploaded to Effis, if any
+    pub upload: Option<Upload>,
+    /// Reqwest HttpClient
+    pub http_client: Client,
+    /// Sender for events produced by spawned tasks
+    pub events: UnboundedSender<(usize, AppEvent)>,
+}
+
+impl Instance {
+    pub fn handle_event(&mut self, event: AppEvent) {
+        match event {
+            AppEvent::Message(PilferMessage::Eludris(msg)) => {
+                if !self.reconcile_echo(&msg) {
+                    let attributes = self.attributes(&msg);
+                    self.push_message(PilferMessage::Eludris(msg), attributes);
+                }
+            }
+            AppEvent::Message(msg) => self.push_message(msg, Attributes::default()),
+            AppEvent::Gateway(event) => {
+                self.handle_gateway_event(event);
+                // Send whatever was written while disconnected
+                self.flush_outbox();
+            }
+            AppEvent::Upload(UploadEvent::Progress(sent)) => {
+                if let Some(upload) = self.upload.as_mut() {
+                    upload.sent = sent;
+                }
+            }
+            AppEvent::Upload(UploadEvent::Done(result)) => {
+                let name = self.upload.take().map(|upload| upload.name);
+                match result {
+                    // Link the file like any other message
+                    Ok(file) => self.queue_message(file_url(&self.info.effis_url, &file)),
+                    Err(err) => self.log(
+                        SystemMessage::new(
+                            Severity::Error,
+                            Some(Source::Http),
+                            format!("Couldn't upload {}", name.unwrap_or_default()),
+                        )
+                        .detail(err),
+                    ),
+                }
+            }
+            // Previews are shared between instances so the main loop takes care of them
+            AppEvent::Preview { .. } => {}
+            AppEvent::Sent { message, result } => {
+                self.sending = false;
+                let echo = self.echoes.iter().position(|echo| echo.id == message.id);
+                match result {
+                    SendResult::Sent(msg) => {
+                        // Nothing left to do if the gateway already delivered it
+                        if let Some(echo) = echo {
+                            let echo = &mut self.echoes[echo];
+                            echo.content = msg.content;
+                            echo.deadline = Some(Instant::now() + ECHO_TIMEOUT);
+                            self.set_outgoing_state(message.id, OutgoingState::Sent);
+                        }
+                    }
+                    SendResult::RateLimited { try_after } => {
+                        if let Some(echo) = echo {
+                            self.echoes.remove(echo);
+                        }
+                        self.log(SystemMessage::new(
+                            Severity::Warn,
+                            Some(Source::Http),
+                            format!(
+                                "Rate limited by Oprish, retrying in {}s",
+                                try_after.as_secs_f32().ceil()
+                            ),
+                        ));
+                        // Put it back in front of the queue, it gets sent automatically later
+                        self.rate_limiter.limited(Instant::now(), try_after);
+                        self.set_outgoing_state(message.id, OutgoingState::Queued);
+                        self.outbox.push_front(message);
+                    }
+                    SendResult::Failed(err) => {
+                        if let Some(echo) = echo {
+                            self.echoes.remove(echo);
+                        }
+                        self.log(
+                            SystemMessage::new(
+                                Severity::Error,
+                                Some(Source::Http),
+                                "Couldn't send message",
+                            )
+                            .detail(&err),
+                        );
+                        self.set_outgoing_state(message.id, OutgoingState::Failed(err))
+                    }
+                }
+                self.flush_outbox();
+            }
+        }
+    }
+
+    /// When the UI next needs to be redrawn on this instance's behalf, if ever.
+    pub fn next_tick(&self) -> Option<Instant> {
+        let now = Instant::now();
+        let gateway = self.gateway.rate_limited_until.filter(|until| *until > now);
+        let echo = self.echoes.iter().filter_map(|echo| echo.deadline).min();
+        gateway
+            .into_iter()
+            .chain(self.rate_limiter.wait_until(now))
+            .chain(echo)
+            .min()
+            .map(|until| until.min(now + Duration::from_secs(1)))
+    }
+
+    /// Handle everything time based.
+    pub fn tick(&mut self) {
+        self.expire_echoes();
+        self.flush_outbox();
+    }
+
+    /// Display a new outgoing message and queue it to be sent.
+    pub fn queue_message(&mut self, content: String) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.push_message(
+            PilferMessage::Outgoing(OutgoingMessage {
+                id,
+                author: self.name.clone(),
+                content: content.clone(),
+                state: OutgoingState::Queued,
+            }),
+            Attributes {
+                own: true,
+                ..Default::default()
+            },
+        );
+        self.outbox.push_back(QueuedMessage { id, content });
+        self.flush_outbox();
+    }
+
+    /// Queue an over-long message as several messages which fit the instance's limit.
+    pub fn queue_split(&mut self, content: &str) {
+        for part in split_message(content, self.info.message_limit) {
+            self.queue_message(part);
+        }
+    }
+
+    /// Upload a file to Effis, sending a link to it once done.
+    pub fn upload(&mut self, path: PathBuf) {
+        let error =
+            |content: String| SystemMessage::new(Severity::Error, Some(Source::Command), content);
+        if self.upload.is_some() {
+            self.log(error(
+                "Already uploading a file, wait for it to finish first".to_string(),
+            ));
+            return;
+        }
+        let size = match fs::metadata(&path) {
+            Ok(meta) if meta.is_file() => meta.len(),
+            Ok(_) => {
+                self.log(error(format!("{} is not a file", path.display())));
+                return;
+            }
+            Err(err) => {
+                self.log(error(format!("Couldn't read {}", path.display())).detail(err));
+                return;
+            }
+        };
+        // Effis would reject it anyway, but only after the whole file was sent
+        if size > self.info.attachment_file_size {
+            self.log(error(format!(
+                "{} is {}, over the instance's {} limit",
+                path.display(),
+                format_size(size),
+                format_size(self.info.attachment_file_size)
+            )));
+            return;
+        }
+        self.upload = Some(Upload {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            sent: 0,
+            total: size,
+        });
+        tokio::spawn(upload_file(
+            self.http_client.clone(),
+            self.info.effis_url.clone(),
+            path,
+            size,
+            self.index,
+            self.events.clone(),
+        ));
+    }
+
+    /// Send the next queued message if nothing is being sent, the gateway is connected and the
+    /// rate limit allows it.
+    ///
+    /// Messages are sent one at a time so they arrive in the order they were written.
+    fn flush_outbox(&mut self) {
+        let now = Instant::now();
+        if self.sending || !self.gateway.connected || self.rate_limiter.wait_until(now).is_some() {
+            return;
+        }
+        let message = match self.outbox.pop_front() {
+            Some(message) => message,
+            None => return,
+        };
+        self.rate_limiter.record(now);
+        self.sending = true;
+        self.set_outgoing_state(message.id, OutgoingState::Sending);
+        // The gateway can beat Oprish's response so start waiting for the echo right away
+        self.echoes.push_back(PendingEcho {
+            id: message.id,
+            content: message.content.clone(),
+            deadline: None,
+        });
+        let request = self
+            .http_client
+            .post(format!("{}/messages/", self.rest_url))
+            .timeout(SEND_TIMEOUT)
+            .json(&json!({"author": self.name, "content": message.content}));
+        tokio::spawn(handle_request(
+            request,
+            message,
+            self.index,
+            self.events.clone(),
+        ));
+    }
+
+    fn set_outgoing_state(&mut self, id: u64, state: OutgoingState) {
+        let message = self
+            .buffer
+            .messages
+            .find_mut(|m| matches!(m, PilferMessage::Outgoing(msg) if msg.id == id));
+        if let Some(message) = message {
+            if let PilferMessage::Outgoing(msg) = message.message_mut() {
+                msg.state = state;
+            }
+        }
+    }
+
+    /// Queue the newest failed message to be sent again.
+    pub fn retry_failed(&mut self) {
+        let message = match self.buffer.messages.find_mut(is_failed) {
+            Some(message) => message,
+            None => return,
+        };
+        if let PilferMessage::Outgoing(msg) = message.message_mut() {
+            msg.state = OutgoingState::Queued;
+            self.outbox.push_back(QueuedMessage {
+                id: msg.id,
+                content: msg.content.clone(),
+            });
+        }
+        self.flush_outbox();
+    }
+
+    /// Remove the newest failed message, returning its content.
+    pub fn take_failed(&mut self) -> Option<String> {
+        match self.buffer.remove(is_failed) {
+            Some(PilferMessage::Outgoing(msg)) => Some(msg.content),
+            _ => None,
+        }
+    }
+
+    /// Drop every message which hasn't been sent yet.
+    pub fn discard_outbox(&mut self) {
+        for queued in self.outbox.drain(..) {
+            self.buffer
+                .remove(|m| matches!(m, PilferMessage::Outgoing(msg) if msg.id == queued.id));
+        }
+    }
+
+    /// Match a message from the gateway with one we sent, marking ours as delivered.
+    ///
+    /// Returns whether it matched, in which case it shouldn't be displayed again.
+    fn reconcile_echo(&mut self, msg: &Message) -> bool {
+        if msg.author != self.name {
+            return false;
+        }
+        match take_echo(&mut self.echoes, &msg.content) {
+            Some(id) => {
+                self.set_outgoing_state(id, OutgoingState::Delivered);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Flag sent messages which took too long to come back through the gateway.
+    fn expire_echoes(&mut self) {
+        for id in take_expired(&mut self.echoes, Instant::now()) {
+            self.set_outgoing_state(id, OutgoingState::Unconfirmed);
+        }
+    }
+
+    fn handle_gateway_event(&mut self, event: GatewayEvent) {
+        let stats = &mut self.gateway;
+        match event {
+            GatewayEvent::Connected => stats.connected = true,
+            GatewayEvent::Disconnected => {
+                stats.connected = false;
+                stats.reconnects += 1;
+            }
+            GatewayEvent::Pong { latency } => {
+                stats.pongs += 1;
+                if latency.is_some() {
+                    stats.latency = latency;
+                }
+            }
+            GatewayEvent::RateLimited { until } => {
+                stats.rate_limits += 1;
+                stats.rate_limited_until = Some(until);
+                let remaining = until.saturating_duration_since(Instant::now());
+                self.log(SystemMessage::new(
+                    Severity::Warn,
+                    Some(Source::Gateway),
+                    format!(
+                        "Rate limited by Pandemonium for {}s",
+                        remaining.as_secs_f32().ceil()
+                    ),
+                ));
+            }
+            GatewayEvent::UnknownFrame(err) => {
+                stats.unknown_frames += 1;
+                stats.last_error = Some(err);
+            }
+            GatewayEvent::UndecodableFrame(err) => {
+                stats.undecodable_frames += 1;
+                stats.last_error = Some(err);
+            }
+        }
+    }
+
+    /// What a message received from the gateway means to the user.
+    pub fn attributes(&self, msg: &Message) -> Attributes {
+        let content = msg.content.to_lowercase();
+        Attributes {
+            mention: is_mention(msg, &self.name),
+            highlight: self
+                .highlights
+                .iter()
+                .any(|word| content.contains(&word.to_lowercase())),
+            own: msg.author == self.name,
+        }
+    }
+
+    pub fn push_message(&mut self, msg: PilferMessage, attributes: Attributes) {
+        if let Err(err) = self.buffer.push(msg, attributes) {
+            self.log(
+                SystemMessage::new(
+                    Severity::Error,
+                    None,
+                    "Couldn't write to the message log, disabling it",
+                )
+                .detail(err),
+            );
+        }
+    }
+
+    /// Add a message to the system log.
+    pub fn log(&self, msg: SystemMessage) {
+        self.events
+            .send((self.index, AppEvent::Message(PilferMessage::System(msg))))
+            .ok();
+    }
+}
+
+/// Whether a message mentions the user.
+pub fn is_mention(msg: &Message, name: &str) -> bool {
+    msg.content.to_lowercase().contains(&name.to_lowercase())
+}
+
+/// Remove the oldest pending echo with the given content, returning the id of its message.
+///
+/// Oprish may trim what it's sent, so the comparison ignores surrounding whitespace.
+fn take_echo(echoes: &mut VecDeque<PendingEcho>, content: &str) -> Option<u64> {
+    let index = echoes
+        .iter()
+        .position(|echo| echo.content.trim() == content.trim())?;
+    echoes.remove(index).map(|echo| echo.id)
+}
+
+/// Remove the pending echoes which are past their deadline, returning the ids of their messages.
+fn take_expired(echoes: &mut VecDeque<PendingEcho>, now: Instant) -> Vec<u64> {
+    let expired: Vec<u64> = echoes
+        .iter()
+        .filter(|echo| matches!(echo.deadline, Some(deadline) if deadline <= now))
+        .map(|echo| echo.id)
+        .collect();
+    echoes.retain(|echo| !expired.contains(&echo.id));
+    expired
+}
+
+fn is_failed(message: &PilferMessage) -> bool {
+    matches!(
+        message,
+        PilferMessage::Outgoing(OutgoingMessage {
+            state: OutgoingState::Failed(_),
+            ..
+        })
+    )
+}
+
+async fn handle_request(
+    request: RequestBuilder,
+    message: QueuedMessage,
+    instance: usize,
+    events: UnboundedSender<(usize, AppEvent)>,
+) {
+    let result = match request.send().await {
+        Ok(res) => match res.json::<MessageResponse>().await {
+            Ok(MessageResponse::Success(msg)) => SendResult::Sent(msg),
+            Ok(MessageResponse::Error(ErrorResponse::RateLimited { try_after, .. })) => {
+                SendResult::RateLimited {
+                    try_after: Duration::from_millis(try_after),
+                }
+            }
+            Ok(MessageResponse::Error(resp)) => SendResult::Failed(format!("{:?}", resp)),
+            Err(err) if err.is_timeout() => {
+                SendResult::Failed(format!("timed out after {}s", SEND_TIMEOUT.as_secs()))
+            }
+            Err(_) => SendResult::Failed("got invalid response".to_string()),
+        },
+        Err(err) if err.is_timeout() => {
+            SendResult::Failed(format!("timed out after {}s", SEND_TIMEOUT.as_secs()))
+        }
+        Err(err) => SendResult::Failed(format!("{:?}", err)),
+    };
+    events
+        .send((instance, AppEvent::Sent { message, result }))
+        .ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(id: u64, content: &str) -> PendingEcho {
+        PendingEcho {
+            id,
+            content: content.to_string(),
+            deadline: None,
+        }
+    }
+
+    #[test]
+    fn echo_before_response() {
+        let mut echoes = VecDeque::from([pending(0, "hi"), pending(1, "  hello there\n")]);
+        // Trimmed by Oprish before broadcasting it
+        assert_eq!(take_echo(&mut echoes, "hello there"), Some(1));
+        assert_eq!(take_echo(&mut echoes, "hello there"), None);
+        // So there's nothing left to wait for once its response arrives
+        assert!(echoes.iter().all(|echo| echo.id != 1));
+        assert!(take_expired(&mut echoes, Instant::now() + ECHO_TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn echo_timeout() {
+        let now = Instant::now();
+        let mut echoes = VecDeque::from([pending(0, "hi"), pending(1, "hi"), pending(2, "hey")]);
+        echoes[0].deadline = Some(now + ECHO_TIMEOUT);
+        echoes[2].deadline = Some(now + 2 * ECHO_TIMEOUT);
+        assert!(take_expired(&mut echoes, now).is_empty());
+        // Only messages Oprish accepted time out
+        assert_eq!(take_expired(&mut echoes, now + ECHO_TIMEOUT), [0]);
+        assert_eq!(take_echo(&mut echoes, "hi"), Some(1));
+        assert_eq!(take_expired(&mut echoes, now + 2 * ECHO_TIMEOUT), [2]);
+        assert!(echoes.is_empty());
+    }
+}
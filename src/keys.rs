@@ -0,0 +1,148 @@
+use std::fmt::{self, Display};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// A key along with the modifiers which have to be held with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub const fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        Self { code, modifiers }
+    }
+
+    /// Parse a binding like `enter`, `alt+enter` or `ctrl+space`.
+    pub fn parse(binding: &str) -> Option<KeyBinding> {
+        let binding = binding.trim().to_lowercase();
+        let mut parts: Vec<&str> = binding.split('+').map(str::trim).collect();
+        let mut code = match parts.pop()? {
+            "enter" | "return" => KeyCode::Enter,
+            "space" => KeyCode::Char(' '),
+            "tab" => KeyCode::Tab,
+            "esc" | "escape" => KeyCode::Esc,
+            "backspace" => KeyCode::Backspace,
+            key => {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return None,
+                }
+            }
+        };
+        let mut modifiers = KeyModifiers::NONE;
+        for part in parts {
+            modifiers |= match part {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return None,
+            };
+        }
+        // Terminals report letters typed with Shift in upper case
+        match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                let mut upper = c.to_uppercase();
+                if let (Some(upper), None) = (upper.next(), upper.next()) {
+                    code = KeyCode::Char(upper);
+                }
+            }
+            _ => {}
+        }
+        Some(KeyBinding { code, modifiers })
+    }
+
+    /// Parse a comma separated list of bindings.
+    pub fn parse_list(bindings: &str) -> Option<Vec<KeyBinding>> {
+        bindings.split(',').map(KeyBinding::parse).collect()
+    }
+
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        key.code == self.code && key.modifiers == self.modifiers
+    }
+}
+
+impl Display for KeyBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift+")?;
+        }
+        match self.code {
+            KeyCode::Enter => write!(f, "Enter"),
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{}", c.to_uppercase()),
+            KeyCode::Tab => write!(f, "Tab"),
+            KeyCode::Esc => write!(f, "Esc"),
+            KeyCode::Backspace => write!(f, "Backspace"),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// The keys which send the input and the ones which insert a newline into it
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    pub send: Vec<KeyBinding>,
+    /// Shift+Enter can only be told apart from Enter in terminals supporting the kitty keyboard
+    /// protocol, the others work everywhere
+    pub newline: Vec<KeyBinding>,
+}
+
+impl KeyBindings {
+    pub fn is_send(&self, key: &KeyEvent) -> bool {
+        self.send.iter().any(|binding| binding.matches(key))
+    }
+
+    pub fn is_newline(&self, key: &KeyEvent) -> bool {
+        self.newline.iter().any(|binding| binding.matches(key))
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            send: vec![KeyBinding::new(KeyCode::Enter, KeyModifiers::NONE)],
+            newline: vec![
+                KeyBinding::new(KeyCode::Enter, KeyModifiers::ALT),
+                KeyBinding::new(KeyCode::Enter, KeyModifiers::SHIFT),
+                KeyBinding::new(KeyCode::Char(' '), KeyModifiers::CONTROL),
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_bindings() {
+        assert_eq!(
+            KeyBinding::parse(" Ctrl + Enter"),
+            Some(KeyBinding::new(KeyCode::Enter, KeyModifiers::CONTROL))
+        );
+        assert_eq!(
+            KeyBinding::parse("alt+space"),
+            Some(KeyBinding::new(KeyCode::Char(' '), KeyModifiers::ALT))
+        );
+        assert_eq!(KeyBinding::parse("hyper+enter"), None);
+        assert_eq!(KeyBinding::parse("ctrl+ab"), None);
+    }
+
+    #[test]
+    fn shifted_letters_match_upper_case() {
+        let binding = KeyBinding::parse("shift+a").unwrap();
+        assert!(binding.matches(&KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT)));
+        assert_eq!(binding.to_string(), "Shift+A");
+        let binding = KeyBinding::parse("ctrl+a").unwrap();
+        assert!(binding.matches(&KeyEvent::new(KeyCode::Char('a'), KeyModifiers::CONTROL)));
+    }
+}
@@ -0,0 +1,19 @@
+#![allow(clippy::uninlined_format_args)]
+
+pub mod buffer;
+pub mod clipboard;
+pub mod complete;
+pub mod gateway;
+pub mod info;
+pub mod input;
+pub mod instance;
+pub mod keys;
+pub mod links;
+pub mod models;
+pub mod preview;
+pub mod ratelimit;
+pub mod split;
+pub mod store;
+pub mod theme;
+pub mod ui;
+pub mod upload;
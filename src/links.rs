@@ -0,0 +1,81 @@
+use std::{
+    env, io,
+    ops::Range,
+    process::{Command, Stdio},
+    thread,
+};
+
+const SCHEMES: [&str; 2] = ["https://", "http://"];
+
+#[cfg(target_os = "macos")]
+const DEFAULT_OPENER: &[&str] = &["open"];
+// Unlike `cmd /C start` this doesn't let cmd interpret the `&`s in URLs
+#[cfg(windows)]
+const DEFAULT_OPENER: &[&str] = &["rundll32", "url.dll,FileProtocolHandler"];
+#[cfg(not(any(target_os = "macos", windows)))]
+const DEFAULT_OPENER: &[&str] = &["xdg-open"];
+
+/// Find every URL in `text`, returning their byte ranges.
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    let mut urls = vec![];
+    let mut offset = 0;
+    while let Some((start, scheme)) = SCHEMES
+        .iter()
+        .filter_map(|scheme| text[offset..].find(scheme).map(|index| (index, scheme)))
+        .min()
+    {
+        let start = offset + start;
+        let end = text[start..]
+            .find(char::is_whitespace)
+            .map_or(text.len(), |index| start + index);
+        let end = start + trim_url(&text[start..end]).len();
+        if end > start + scheme.len() {
+            urls.push(start..end);
+        }
+        offset = end.max(start + scheme.len());
+    }
+    urls
+}
+
+/// Strip punctuation which most likely ends the sentence rather than the URL.
+fn trim_url(url: &str) -> &str {
+    let mut url = url;
+    while let Some(last) = url.chars().last() {
+        let unbalanced = match last {
+            ')' => url.matches('(').count() < url.matches(')').count(),
+            ']' => url.matches('[').count() < url.matches(']').count(),
+            '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' | '>' | '*' | '_' | '`' => true,
+            _ => false,
+        };
+        if !unbalanced {
+            break;
+        }
+        url = &url[..url.len() - last.len_utf8()];
+    }
+    url
+}
+
+/// Open a URL with `$BROWSER`, falling back to the platform's default opener.
+pub fn open_url(url: &str) -> io::Result<()> {
+    // $BROWSER can be a colon separated list of commands, the first one is good enough
+    let browser = env::var("BROWSER").unwrap_or_default();
+    let mut command: Vec<&str> = browser
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .collect();
+    if command.is_empty() {
+        command = DEFAULT_OPENER.to_vec();
+    }
+    let mut child = Command::new(command[0])
+        .args(&command[1..])
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Reap it once it exits without holding up the UI
+    thread::spawn(move || child.wait());
+    Ok(())
+}
@@ -1,12 +1,13 @@
 #![allow(clippy::uninlined_format_args)]
 
-mod gateway;
-mod models;
-mod ui;
-
 use crossterm::{
     cursor::{CursorShape, SetCursorShape},
-    event::{self, DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyModifiers},
+    event::{
+        DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
+        EnableFocusChange, EnableMouseCapture, Event, EventStream, KeyCode, KeyModifiers,
+        KeyboardEnhancementFlags, MouseButton, MouseEvent, MouseEventKind,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
@@ -14,55 +15,109 @@ use discord_rich_presence::{
     activity::{Activity, Assets, Button, Timestamps},
     DiscordIpc, DiscordIpcClient,
 };
-use gateway::handle_gateway;
-use models::{AppContext, MessageResponse, PilferMessage, SystemMessage};
-use reqwest::{Client, RequestBuilder};
-use serde_json::json;
+use futures::{future, StreamExt};
+use pilfer::{
+    buffer::{Buffer, Tab},
+    clipboard,
+    complete::{complete_path, expand_home},
+    gateway::handle_gateway,
+    info::{describe_info, fetch_info},
+    input::Input,
+    instance::Instance,
+    keys::{KeyBinding, KeyBindings},
+    links::{find_urls, open_url},
+    models::{
+        AppContext, AppEvent, Attributes, GatewayStats, Notice, PilferMessage, Popup, Severity,
+        Source, SystemMessage,
+    },
+    preview::{Previews, Protocol},
+    ratelimit::RateLimiter,
+    store::MessageStore,
+    theme::Theme,
+    ui::{column_offset, draw_hyperlinks, input_lines, ui, ScreenLayout},
+};
+use reqwest::Client;
 use std::{
+    collections::VecDeque,
     env,
     error::Error,
+    fs::{self, File, OpenOptions},
     io::{self, Write},
+    path::PathBuf,
+    process::{self, Command},
     sync::atomic::{AtomicBool, Ordering},
-    sync::{Arc, Mutex},
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
     vec,
 };
-use todel::models::{ErrorResponse, InstanceInfo};
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver},
+    task, time,
+};
 use tui::{
     backend::{Backend, CrosstermBackend},
-    style::{Color, Style},
+    layout::Rect,
     Terminal,
 };
-use ui::ui;
 
 pub const REST_URL: &str = "https://eludris.tooty.xyz/";
 pub const PILFER_APP_ID: &str = "1028728489165193247";
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const DEFAULT_SCROLLBACK: usize = 10000;
+/// How many messages PageUp and PageDown scroll by
+pub const SCROLL_STEP: usize = 10;
+/// Pastes with more lines than this need Enter to be pressed twice to be sent
+pub const LARGE_PASTE_LINES: usize = 10;
+/// Pastes longer than this in bytes need Enter to be pressed twice to be sent
+pub const LARGE_PASTE_SIZE: usize = 1000;
+/// How many messages the mouse wheel scrolls by
+pub const MOUSE_SCROLL_STEP: usize = 3;
+/// How long system messages are shown below the chat
+pub const NOTICE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Used to compose messages when neither `$VISUAL` nor `$EDITOR` are set
+pub const DEFAULT_EDITOR: &str = "vi";
+/// How many of the newest messages the link picker looks through
+pub const LINK_PICKER_MESSAGES: usize = 100;
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |p| {
-        disable_raw_mode().unwrap();
-        let terminal = CrosstermBackend::new(io::stdout());
-        let mut terminal = Terminal::new(terminal).unwrap();
-        execute!(
-            terminal.backend_mut(),
-            LeaveAlternateScreen,
-            DisableFocusChange,
-            SetCursorShape(CursorShape::Block),
-        )
-        .unwrap();
+        // Failing to restore the terminal shouldn't hide the panic message
+        leave_terminal(&mut io::stdout()).ok();
         hook(p);
     }));
     let mut stdout = io::stdout();
 
+    // Several instances can be connected to at once by separating their URLs with commas
+    let rest_urls: Vec<String> = env::var("INSTANCE_URL")
+        .unwrap_or_else(|_| REST_URL.to_string())
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .collect();
+    if rest_urls.is_empty() {
+        anyhow::bail!("Invalid INSTANCE_URL supplied, it has to contain at least one URL");
+    }
+    let http_client = Client::new();
+
     // Get a name that complies with Eludris' 2-32 name character limit
     let name = match env::args().nth(1) {
         Some(name) => {
             if name == "-v" || name == "--version" {
                 println!("Version: {}", VERSION);
                 return Ok(());
+            } else if name == "instance-info" {
+                for (i, rest_url) in rest_urls.iter().enumerate() {
+                    if i > 0 {
+                        println!();
+                    }
+                    let info = fetch_info(&http_client, rest_url).await?;
+                    for line in describe_info(&info) {
+                        println!("{}", line);
+                    }
+                }
+                return Ok(());
             } else if name.len() < 2 || name.len() > 32 {
                 anyhow::bail!("Invalid name supplied, your name has to be between 2 and 32 characters long, try again!");
             }
@@ -86,16 +141,71 @@ async fn main() -> Result<(), anyhow::Error> {
         }),
     };
 
-    let rest_url = env::var("INSTANCE_URL").unwrap_or_else(|_| REST_URL.to_string());
-    let http_client = Client::new();
-    let info: InstanceInfo = http_client
-        .get(&rest_url)
-        .send()
-        .await
-        .expect("Can not connect to Oprish")
-        .json()
-        .await
-        .expect("Server returned a malformed info response");
+    let scrollback = match env::var("PILFER_SCROLLBACK") {
+        Ok(limit) => match limit.parse::<usize>() {
+            Ok(limit) if limit > 0 => limit,
+            _ => {
+                anyhow::bail!("Invalid PILFER_SCROLLBACK supplied, it has to be a positive number")
+            }
+        },
+        Err(_) => DEFAULT_SCROLLBACK,
+    };
+    let log_path = env::var_os("PILFER_LOG").map(PathBuf::from);
+    let theme = match env::var("PILFER_THEME") {
+        Ok(name) => match Theme::by_name(&name) {
+            Some(theme) => theme,
+            None => anyhow::bail!(
+                "Invalid PILFER_THEME supplied, it has to be one of {}",
+                Theme::NAMES.join(", ")
+            ),
+        },
+        Err(_) => Theme::default(),
+    };
+    let highlights: Vec<String> = env::var("PILFER_HIGHLIGHT")
+        .unwrap_or_default()
+        .split(',')
+        .map(|word| word.trim().to_string())
+        .filter(|word| !word.is_empty())
+        .collect();
+    // Terminals without OSC 8 support might print the escape sequences as is
+    let hyperlinks = !matches!(
+        env::var("PILFER_HYPERLINKS").as_deref(),
+        Ok("0" | "false" | "off" | "no")
+    );
+    let mut keys = KeyBindings::default();
+    if let Ok(bindings) = env::var("PILFER_SEND_KEYS") {
+        match KeyBinding::parse_list(&bindings) {
+            Some(bindings) => keys.send = bindings,
+            None => anyhow::bail!("Invalid PILFER_SEND_KEYS supplied, it has to be a comma separated list of keys like enter or ctrl+enter"),
+        }
+    }
+    if let Ok(bindings) = env::var("PILFER_NEWLINE_KEYS") {
+        match KeyBinding::parse_list(&bindings) {
+            Some(bindings) => keys.newline = bindings,
+            None => anyhow::bail!("Invalid PILFER_NEWLINE_KEYS supplied, it has to be a comma separated list of keys like alt+enter or shift+enter"),
+        }
+    }
+    // Capturing the mouse gets in the way of selecting text in most terminals
+    let mouse = !matches!(
+        env::var("PILFER_MOUSE").as_deref(),
+        Ok("0" | "false" | "off" | "no")
+    );
+    let previews = match env::var("PILFER_PREVIEWS") {
+        Ok(name) if name != "off" => match Protocol::by_name(&name) {
+            Some(protocol) => Previews::new(Some(protocol)),
+            None => anyhow::bail!(
+                "Invalid PILFER_PREVIEWS supplied, it has to be one of off, {}",
+                Protocol::NAMES.join(", ")
+            ),
+        },
+        _ => Previews::new(None),
+    };
+    let infos = future::try_join_all(
+        rest_urls
+            .iter()
+            .map(|rest_url| fetch_info(&http_client, rest_url)),
+    )
+    .await?;
 
     // Discord rich presence stuff
     let mut client = DiscordIpcClient::new(PILFER_APP_ID).unwrap();
@@ -116,11 +226,19 @@ async fn main() -> Result<(), anyhow::Error> {
             Button::new("Pilfer", "https://github.com/eludris/pilfer/"),
         ];
 
+        let instance_names: Vec<&str> = infos
+            .iter()
+            .map(|info| info.instance_name.as_str())
+            .collect();
         client
             .set_activity(
                 Activity::new()
                     .details("Chatting on Eludris")
-                    .state(&format!("Talking on {} as {}", info.instance_name, name))
+                    .state(&format!(
+                        "Talking on {} as {}",
+                        instance_names.join(", "),
+                        name
+                    ))
                     .assets(assets)
                     .timestamps(timestamp)
                     .buttons(buttons),
@@ -128,51 +246,95 @@ async fn main() -> Result<(), anyhow::Error> {
             .unwrap();
     }
 
-    enable_raw_mode()?;
-    execute!(
-        stdout,
-        EnterAlternateScreen,
-        EnableFocusChange,
-        SetCursorShape(CursorShape::Line),
-    )?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
-
-    let messages = Arc::new(Mutex::new(vec![]));
+    let (events, events_rx) = mpsc::unbounded_channel();
 
     let focused = Arc::new(AtomicBool::new(true));
     #[cfg(target_os = "linux")]
-    let notification = Arc::new(Mutex::new(None));
+    let notification = Arc::new(std::sync::Mutex::new(None));
 
-    let app = AppContext {
-        input: String::new(),
-        name: name.clone(),
-        messages: Arc::clone(&messages),
-        http_client,
-        rest_url,
-        focused: Arc::clone(&focused),
-        #[cfg(target_os = "linux")]
-        notification: Arc::clone(&notification),
-    };
+    let multiple = infos.len() > 1;
+    let mut instances = vec![];
+    for (index, (rest_url, info)) in rest_urls.into_iter().zip(infos).enumerate() {
+        // Every instance gets its own log, numbered in the order they were given in
+        let log_path = log_path.as_ref().map(|path| {
+            if multiple {
+                let mut path = path.clone().into_os_string();
+                path.push(format!(".{}", index + 1));
+                PathBuf::from(path)
+            } else {
+                path.clone()
+            }
+        });
+        let messages = match MessageStore::new(scrollback, log_path.as_deref()) {
+            Ok(messages) => messages,
+            Err(err) => anyhow::bail!("Could not open PILFER_LOG: {}", err),
+        };
+
+        tokio::spawn(handle_gateway(
+            info.pandemonium_url.clone(),
+            index,
+            events.clone(),
+            Arc::clone(&focused),
+            #[cfg(target_os = "linux")]
+            Arc::clone(&notification),
+        ));
+
+        instances.push(Instance {
+            index,
+            rest_url,
+            name: name.clone(),
+            highlights: highlights.clone(),
+            buffer: Buffer::new(messages),
+            gateway: GatewayStats::default(),
+            outbox: VecDeque::new(),
+            next_id: 0,
+            sending: false,
+            echoes: VecDeque::new(),
+            upload: None,
+            rate_limiter: RateLimiter::new(
+                info.rate_limits
+                    .as_ref()
+                    .map(|limits| &limits.oprish.message_create),
+            ),
+            info,
+            http_client: http_client.clone(),
+            events: events.clone(),
+        });
+    }
+
+    enter_terminal(&mut stdout, mouse)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
 
-    tokio::spawn(handle_gateway(
-        info.pandemonium_url,
-        messages,
+    let app = AppContext {
+        input: Input::default(),
+        name,
+        instances,
+        current: 0,
+        tab: Tab::Instance(0),
+        mentions: Buffer::new(MessageStore::new(scrollback, None)?),
+        search: Buffer::new(MessageStore::new(scrollback, None)?),
+        search_query: None,
+        system: Buffer::new(MessageStore::new(scrollback, None)?),
+        notice: None,
+        theme,
+        hyperlinks,
+        previews,
+        selecting: false,
+        warn_before_send: false,
+        keys,
+        mouse,
+        screen: ScreenLayout::default(),
+        show_debug: false,
+        popup: None,
         focused,
         #[cfg(target_os = "linux")]
         notification,
-        name,
-    ));
+    };
 
-    let res = run_app(&mut terminal, app);
+    let res = run_app(&mut terminal, app, events_rx).await;
 
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableFocusChange,
-        SetCursorShape(CursorShape::Block),
-    )?;
+    leave_terminal(terminal.backend_mut())?;
 
     if let Err(err) = res {
         println!("{:?}", err)
@@ -181,58 +343,209 @@ async fn main() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn run_app<B: Backend>(
+/// Take the terminal over for the UI.
+fn enter_terminal(out: &mut impl Write, mouse: bool) -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(
+        out,
+        EnterAlternateScreen,
+        EnableFocusChange,
+        SetCursorShape(CursorShape::Line),
+    )?;
+    // Pastes are typed out key by key in the legacy Windows console, which refuses this
+    execute!(out, EnableBracketedPaste).ok();
+    // Lets Shift+Enter be told apart from Enter, terminals which don't support it ignore this
+    // and the legacy Windows console refuses it
+    execute!(
+        out,
+        PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+    )
+    .ok();
+    if mouse {
+        execute!(out, EnableMouseCapture)?;
+    }
+    Ok(())
+}
+
+/// Hand the terminal back the way it was before the UI took it over.
+fn leave_terminal(out: &mut impl Write) -> io::Result<()> {
+    // The flags are kept per screen, so they have to go before leaving the alternate one
+    execute!(out, PopKeyboardEnhancementFlags).ok();
+    execute!(out, DisableBracketedPaste).ok();
+    disable_raw_mode()?;
+    execute!(
+        out,
+        LeaveAlternateScreen,
+        DisableFocusChange,
+        DisableMouseCapture,
+        SetCursorShape(CursorShape::Block),
+    )
+}
+
+async fn run_app<B: Backend + Write>(
     terminal: &mut Terminal<B>,
     mut app: AppContext,
+    mut events: UnboundedReceiver<(usize, AppEvent)>,
 ) -> Result<(), Box<dyn Error>> {
+    let mut input = EventStream::new();
+    // Only redraw when something actually changed since the last frame
+    let mut dirty = true;
     loop {
-        terminal.draw(|f| ui(f, &app))?;
-
-        if event::poll(Duration::from_millis(100))? {
-            let event = event::read()?;
-            match event {
-                Event::FocusGained => {
-                    app.focused.store(true, Ordering::Relaxed);
-                    // Kill the displayed notification if it currently exists
-                    #[cfg(target_os = "linux")]
-                    if let Some(notif) = app.notification.lock().unwrap().take() {
-                        notif.close();
-                    }
-                }
-                Event::FocusLost => app.focused.store(false, Ordering::Relaxed),
-                Event::Key(key) => match key.code {
-                    KeyCode::Enter => {
-                        // Send a message
-                        if !app.input.is_empty() {
-                            let request = app
-                                .http_client
-                                .post(format!("{}/messages/", app.rest_url))
-                                .json(
-                                    &json!({"author": app.name, "content": app.input.drain(..).collect::<String>()})
-                                );
-                            let messages = Arc::clone(&app.messages);
-                            tokio::spawn(handle_request(request, messages));
+        if dirty {
+            terminal.draw(|f| ui(f, &mut app))?;
+            if app.previews.needs_clear() {
+                terminal.clear()?;
+                terminal.draw(|f| ui(f, &mut app))?;
+            }
+            app.previews.draw(terminal.backend_mut())?;
+            draw_hyperlinks(terminal.backend_mut(), &app.screen.hyperlinks)?;
+            dirty = false;
+        }
+
+        let tick = next_tick(&app);
+        tokio::select! {
+            event = input.next() => {
+                let event = match event {
+                    Some(event) => event?,
+                    None => break,
+                };
+                match event {
+                    Event::FocusGained => {
+                        app.focused.store(true, Ordering::Relaxed);
+                        // Kill the displayed notification if it currently exists
+                        #[cfg(target_os = "linux")]
+                        if let Some(notif) = app.notification.lock().unwrap().take() {
+                            notif.close();
                         }
                     }
-                    KeyCode::Char(c) => {
-                        // Keybingings go here
-                        if key.modifiers.contains(KeyModifiers::CONTROL) {
-                            match c {
-                                'c' => break,
-                                'l' => app.messages.lock().unwrap().clear(),
-                                ' ' => app.input.push('\n'),
-                                _ => {}
+                    Event::FocusLost => app.focused.store(false, Ordering::Relaxed),
+                    Event::Resize(_, _) => {
+                        // Resizing clears the screen along with any previews on it
+                        app.previews.invalidate();
+                        dirty = true;
+                    }
+                    // Popups take all input except for the global keybindings
+                    Event::Key(key)
+                        if app.popup.is_some()
+                            && !key.modifiers.contains(KeyModifiers::CONTROL) =>
+                    {
+                        dirty = handle_popup_key(&mut app, key.code);
+                    }
+                    // So does selection mode, where keys act on the selected message
+                    Event::Key(key)
+                        if app.selecting
+                            && !key
+                                .modifiers
+                                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+                    {
+                        dirty = handle_selection_key(&mut app, key.code);
+                    }
+                    Event::Paste(text) if app.popup.is_none() => {
+                        paste(&mut app, &text);
+                        dirty = true;
+                    }
+                    Event::Mouse(mouse) if app.popup.is_none() => {
+                        dirty = handle_mouse(&mut app, mouse);
+                    }
+                    Event::Key(key) => {
+                        dirty = true;
+                        match key.code {
+                            _ if app.keys.is_newline(&key) => app.input.insert('\n'),
+                            // Big pastes need to be sent twice, unless they were split or
+                            // deleted since
+                            _ if app.keys.is_send(&key)
+                                && app.warn_before_send
+                                && is_large(app.input.text()) =>
+                            {
+                                warn_large_input(&mut app)
+                            }
+                            // Send a message
+                            _ if app.keys.is_send(&key) && !app.input.is_empty() => {
+                                // Messages over the instance's limit would just get rejected by
+                                // Oprish
+                                if !run_command(&mut app)
+                                    && app.input.len() <= app.instance().info.message_limit
+                                {
+                                    let content = app.input.take();
+                                    app.instance_mut().queue_message(content);
+                                }
+                            }
+                            // Switch between tabs
+                            KeyCode::Char(c @ '1'..='9')
+                                if key.modifiers.contains(KeyModifiers::ALT) =>
+                            {
+                                match app.tabs().get(c as usize - '1' as usize) {
+                                    Some(tab) => app.select_tab(*tab),
+                                    None => dirty = false,
+                                }
                             }
-                        } else {
-                            app.input.push(c);
+                            KeyCode::Left if key.modifiers.contains(KeyModifiers::ALT) => {
+                                cycle_tab(&mut app, false)
+                            }
+                            KeyCode::Right if key.modifiers.contains(KeyModifiers::ALT) => {
+                                cycle_tab(&mut app, true)
+                            }
+                            KeyCode::Char(c) => {
+                                // Keybingings go here
+                                if key.modifiers.contains(KeyModifiers::CONTROL) {
+                                    match c {
+                                        'c' => break,
+                                        'l' => app.buffer_mut(app.tab).clear(),
+                                        'r' => app.instance_mut().retry_failed(),
+                                        'e' => edit_failed(&mut app),
+                                        'd' => app.instance_mut().discard_outbox(),
+                                        's' => split_input(&mut app),
+                                        'o' => open_link_picker(&mut app),
+                                        'g' => {
+                                            // It would eat the keys meant for the editor,
+                                            // dropping it stops its reader thread
+                                            drop(input);
+                                            compose_in_editor(terminal, &mut app).await?;
+                                            input = EventStream::new();
+                                        }
+                                        'k' if app.selecting => stop_selecting(&mut app),
+                                        'k' => start_selecting(&mut app),
+                                        _ => dirty = false,
+                                    }
+                                } else {
+                                    app.input.insert(c);
+                                }
+                            }
+                            KeyCode::Backspace => app.input.backspace(),
+                            KeyCode::Delete => app.input.delete(),
+                            KeyCode::Left => app.input.left(),
+                            KeyCode::Right => app.input.right(),
+                            KeyCode::Home => app.input.home(),
+                            KeyCode::End => app.input.end(),
+                            KeyCode::Tab => complete_input(&mut app),
+                            KeyCode::Esc => start_selecting(&mut app),
+                            KeyCode::PageUp => scroll_up(&mut app, SCROLL_STEP),
+                            KeyCode::PageDown => scroll_down(&mut app, SCROLL_STEP),
+                            KeyCode::F(2) => toggle_mouse(terminal, &mut app)?,
+                            KeyCode::F(12) => app.show_debug = !app.show_debug,
+                            _ => dirty = false,
                         }
                     }
-                    KeyCode::Backspace => {
-                        app.input.pop();
-                    }
                     _ => {}
-                },
-                _ => {}
+                }
+            }
+            Some((index, event)) = events.recv() => {
+                handle_event(&mut app, index, event);
+                // Drain whatever else is already queued so bursts only cause one redraw
+                while let Ok((index, event)) = events.try_recv() {
+                    handle_event(&mut app, index, event);
+                }
+                dirty = true;
+            }
+            // Keep countdowns up to date and send queued messages once allowed
+            _ = time::sleep_until(tick.unwrap_or_else(Instant::now).into()), if tick.is_some() => {
+                for instance in app.instances.iter_mut() {
+                    instance.tick();
+                }
+                if matches!(&app.notice, Some(notice) if notice.until <= Instant::now()) {
+                    app.notice = None;
+                }
+                dirty = true;
             }
         }
     }
@@ -240,47 +553,588 @@ fn run_app<B: Backend>(
     Ok(())
 }
 
-async fn handle_request(
-    request: RequestBuilder,
-    messages: Arc<Mutex<Vec<(PilferMessage, Style)>>>,
-) {
-    let res = request.send().await;
-    match res {
-        Ok(res) => match res.json::<MessageResponse>().await {
-            Ok(resp) => match resp {
-                MessageResponse::Error(resp) => match resp {
-                    ErrorResponse::RateLimited { try_after, .. } => {
-                        messages.lock().unwrap().push((
-                            PilferMessage::System(SystemMessage {
-                                content: format!(
-                                    "System: You've been ratelimited, try in {}s",
-                                    try_after / 1000
-                                ),
-                            }),
-                            Style::default().fg(Color::Red),
-                        ))
-                    }
-                    _ => messages.lock().unwrap().push((
-                        PilferMessage::System(SystemMessage {
-                            content: format!("System: Couldn't send message: {:?}", resp),
-                        }),
-                        Style::default().fg(Color::Red),
-                    )),
-                },
-                MessageResponse::Success(_) => {}
-            },
-            Err(_) => messages.lock().unwrap().push((
-                PilferMessage::System(SystemMessage {
-                    content: "System: Couldn't send message: got invalid response".to_string(),
-                }),
-                Style::default().fg(Color::Red),
-            )),
+/// Run the command in the input if it is one, returning whether it was.
+fn run_command(app: &mut AppContext) -> bool {
+    let input = app.input.text().trim();
+    if input == "/info" {
+        app.popup = Some(Popup::Info { scroll: 0 });
+    } else if let Some(query) = input.strip_prefix("/search ") {
+        let query = query.trim().to_string();
+        search(app, query);
+    } else if let Some(path) = input.strip_prefix("/upload ") {
+        let path = expand_home(path.trim());
+        app.instance_mut().upload(path);
+    } else if let Some(name) = input.strip_prefix("/theme ") {
+        // Messages are styled when rendered so this restyles the whole history too
+        match Theme::by_name(name.trim()) {
+            Some(theme) => app.theme = theme,
+            None => {
+                let msg = SystemMessage::new(
+                    Severity::Error,
+                    Some(Source::Command),
+                    format!(
+                        "Unknown theme {}, the available themes are {}",
+                        name.trim(),
+                        Theme::NAMES.join(", ")
+                    ),
+                );
+                log_system(app, msg);
+            }
+        }
+    } else if let Some(path) = input.strip_prefix("/exportlog ") {
+        let path = path.trim().to_string();
+        let msg = match export_log(app, &path) {
+            Ok(count) => SystemMessage::new(
+                Severity::Info,
+                Some(Source::Command),
+                format!("Exported {} system messages to {}", count, path),
+            ),
+            Err(err) => SystemMessage::new(
+                Severity::Error,
+                Some(Source::Command),
+                format!("Couldn't export the system log to {}", path),
+            )
+            .detail(err),
+        };
+        log_system(app, msg);
+    } else {
+        return false;
+    }
+    app.input.clear();
+    true
+}
+
+/// Complete the path of an `/upload` command, listing the candidates if it's ambiguous.
+fn complete_input(app: &mut AppContext) {
+    let partial = match app.input.text().strip_prefix("/upload ") {
+        Some(partial) => partial.trim_start(),
+        None => return,
+    };
+    let (completed, candidates) = complete_path(partial);
+    app.input.set(format!("/upload {}", completed));
+    if candidates.len() > 1 {
+        app.notice = Some(Notice {
+            severity: Severity::Info,
+            content: candidates.join("  "),
+            until: Instant::now() + NOTICE_TIMEOUT,
+        });
+    }
+}
+
+/// Fill the search tab with the current instance's messages containing `query` and show it.
+fn search(app: &mut AppContext, query: String) {
+    let needle = query.to_lowercase();
+    let results: Vec<_> = app
+        .instance()
+        .buffer
+        .messages
+        .iter()
+        .filter(|m| m.message().to_string().to_lowercase().contains(&needle))
+        .map(|m| (m.message().clone(), m.attributes))
+        .collect();
+    app.search.clear();
+    for (message, attributes) in results {
+        app.search.push(message, attributes).ok();
+    }
+    app.search_query = Some(query);
+    app.select_tab(Tab::Search);
+}
+
+/// Switch to the next or previous tab, wrapping around.
+fn cycle_tab(app: &mut AppContext, forward: bool) {
+    let tabs = app.tabs();
+    let index = tabs.iter().position(|tab| *tab == app.tab).unwrap_or(0);
+    let index = if forward {
+        (index + 1) % tabs.len()
+    } else {
+        (index + tabs.len() - 1) % tabs.len()
+    };
+    app.select_tab(tabs[index]);
+}
+
+/// Hand an event to its instance, collecting mentions and system messages in their own tabs.
+fn handle_event(app: &mut AppContext, index: usize, event: AppEvent) {
+    if let AppEvent::Message(PilferMessage::Eludris(msg)) = &event {
+        request_previews(app, index, &msg.content);
+    }
+    match event {
+        AppEvent::Message(PilferMessage::System(mut msg)) => {
+            if app.instances.len() > 1 {
+                msg.content = format!(
+                    "{}: {}",
+                    app.instances[index].info.instance_name, msg.content
+                );
+            }
+            log_system(app, msg);
+        }
+        AppEvent::Message(PilferMessage::Eludris(msg))
+            if msg.author != app.instances[index].name =>
+        {
+            let attributes = app.instances[index].attributes(&msg);
+            if app.tab != Tab::Instance(index) {
+                app.instances[index].buffer.mark_unread(attributes.mention);
+            }
+            if attributes.mention {
+                // The mentions buffer has no log to fail writing to
+                app.mentions
+                    .push(PilferMessage::Eludris(msg.clone()), attributes)
+                    .ok();
+                if app.tab != Tab::Mentions {
+                    app.mentions.mark_unread(true);
+                }
+            }
+            app.instances[index].handle_event(AppEvent::Message(PilferMessage::Eludris(msg)));
+        }
+        AppEvent::Preview { url, result } => {
+            if let Err(err) = &result {
+                log_system(
+                    app,
+                    SystemMessage::new(
+                        Severity::Warn,
+                        Some(Source::Http),
+                        "Couldn't load an image preview",
+                    )
+                    .detail(err),
+                );
+            }
+            app.previews.finish(url, result);
+        }
+        event => app.instances[index].handle_event(event),
+    }
+}
+
+/// Start loading previews of the Effis links in a message.
+fn request_previews(app: &mut AppContext, index: usize, content: &str) {
+    let instance = &app.instances[index];
+    let effis_url = instance.info.effis_url.trim_end_matches('/');
+    for range in find_urls(content) {
+        let url = &content[range];
+        if matches!(url.strip_prefix(effis_url), Some(path) if path.starts_with('/')) {
+            app.previews
+                .request(url, &instance.http_client, index, &instance.events);
+        }
+    }
+}
+
+/// Insert pasted text into the input as is, newlines included.
+fn paste(app: &mut AppContext, text: &str) {
+    // Some terminals send carriage returns instead of newlines
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+    if is_large(&text) {
+        app.warn_before_send = true;
+    }
+    app.input.insert_str(&text);
+}
+
+fn is_large(text: &str) -> bool {
+    text.lines().count() > LARGE_PASTE_LINES || text.len() > LARGE_PASTE_SIZE
+}
+
+/// Make sure a big paste isn't sent by accident, the next Enter sends it.
+fn warn_large_input(app: &mut AppContext) {
+    app.warn_before_send = false;
+    app.notice = Some(Notice {
+        severity: Severity::Warn,
+        content: format!(
+            "The input is {} lines and {} bytes long, press {} again to send it",
+            app.input.text().lines().count(),
+            app.input.len(),
+            app.keys.send[0]
+        ),
+        until: Instant::now() + NOTICE_TIMEOUT,
+    });
+}
+
+/// Scroll the active tab towards older messages.
+fn scroll_up(app: &mut AppContext, amount: usize) {
+    if let Err(err) = app.buffer_mut(app.tab).scroll_up(amount) {
+        log_system(
+            app,
+            SystemMessage::new(Severity::Error, None, "Couldn't read from the message log")
+                .detail(err),
+        );
+    }
+}
+
+/// Scroll the active tab towards newer messages.
+fn scroll_down(app: &mut AppContext, amount: usize) {
+    if let Err(err) = app.buffer_mut(app.tab).scroll_down(amount) {
+        log_system(
+            app,
+            SystemMessage::new(Severity::Error, None, "Couldn't read from the message log")
+                .detail(err),
+        );
+    }
+}
+
+/// Handle a mouse event, returning whether anything changed.
+fn handle_mouse(app: &mut AppContext, mouse: MouseEvent) -> bool {
+    let (x, y) = (mouse.column, mouse.row);
+    let screen = &app.screen;
+    match mouse.kind {
+        MouseEventKind::ScrollUp => scroll_up(app, MOUSE_SCROLL_STEP),
+        MouseEventKind::ScrollDown => scroll_down(app, MOUSE_SCROLL_STEP),
+        // Move the input's cursor to the clicked character
+        MouseEventKind::Down(MouseButton::Left) if contains(screen.input, x, y) => {
+            let lines = input_lines(
+                app.input.text(),
+                screen.input.width.saturating_sub(2) as usize,
+            );
+            let row = (y.saturating_sub(screen.input.y + 1) as usize) + screen.input_scroll;
+            let offset = match lines.get(row) {
+                Some((start, line)) => {
+                    start + column_offset(line, x.saturating_sub(screen.input.x + 1) as usize)
+                }
+                None => app.input.len(),
+            };
+            app.input.set_cursor(offset);
+        }
+        MouseEventKind::Down(MouseButton::Left) if contains(screen.messages, x, y) => {
+            let link = screen.links.iter().find(|(link_x, link_y, link)| {
+                let start = link_x + link.column as u16;
+                *link_y == y && x >= start && x < start + link.width as u16
+            });
+            if let Some((_, _, link)) = link {
+                let url = link.url.clone();
+                open_link(app, &url);
+                return true;
+            }
+            // Rows are counted from the bottom of the list, inside its border
+            let row = (screen.messages.bottom() - 2).checked_sub(y);
+            let index = match row.and_then(|row| screen.rows.get(row as usize)) {
+                Some(index) => *index,
+                None => return false,
+            };
+            let buffer = app.buffer_mut(app.tab);
+            // Clicking the selected message again deselects it
+            buffer.selected = if buffer.selected == Some(index) {
+                None
+            } else {
+                Some(index)
+            };
+            // Only highlighted so typing goes on into the input, selection mode starts from it
+            app.selecting &= buffer.selected.is_some();
+        }
+        _ => return false,
+    }
+    true
+}
+
+fn contains(area: Rect, x: u16, y: u16) -> bool {
+    x >= area.x && x < area.right() && y >= area.y && y < area.bottom()
+}
+
+/// Turn mouse capture on or off, so the terminal's own text selection can be used.
+fn toggle_mouse<B: Backend + Write>(
+    terminal: &mut Terminal<B>,
+    app: &mut AppContext,
+) -> io::Result<()> {
+    app.mouse = !app.mouse;
+    let content = if app.mouse {
+        execute!(terminal.backend_mut(), EnableMouseCapture)?;
+        "Mouse capture on, F2 to use the terminal's text selection"
+    } else {
+        execute!(terminal.backend_mut(), DisableMouseCapture)?;
+        "Mouse capture off, F2 to turn it back on"
+    };
+    app.notice = Some(Notice {
+        severity: Severity::Info,
+        content: content.to_string(),
+        until: Instant::now() + NOTICE_TIMEOUT,
+    });
+    Ok(())
+}
+
+/// Add a message to the system log, briefly showing it below the chat.
+fn log_system(app: &mut AppContext, msg: SystemMessage) {
+    if app.tab != Tab::System {
+        app.system.mark_unread(false);
+        app.notice = Some(Notice {
+            severity: msg.severity,
+            content: msg.content.clone(),
+            until: Instant::now() + NOTICE_TIMEOUT,
+        });
+    }
+    app.system
+        .push(PilferMessage::System(msg), Attributes::default())
+        .ok();
+}
+
+/// Write the system log to `path`, one message per line.
+fn export_log(app: &AppContext, path: &str) -> io::Result<usize> {
+    let mut file = File::create(path)?;
+    for message in app.system.messages.iter() {
+        writeln!(file, "{}", message.message())?;
+    }
+    Ok(app.system.messages.len())
+}
+
+/// Handle a key press while a popup is open, returning whether anything changed.
+fn handle_popup_key(app: &mut AppContext, code: KeyCode) -> bool {
+    let popup = match app.popup.as_mut() {
+        Some(popup) => popup,
+        None => return false,
+    };
+    match (popup, code) {
+        (_, KeyCode::Esc | KeyCode::Char('q')) => app.popup = None,
+        (Popup::Info { scroll }, KeyCode::Up) => *scroll = scroll.saturating_sub(1),
+        (Popup::Info { scroll }, KeyCode::Down) => *scroll = scroll.saturating_add(1),
+        (Popup::Info { scroll }, KeyCode::PageUp) => *scroll = scroll.saturating_sub(10),
+        (Popup::Info { scroll }, KeyCode::PageDown) => *scroll = scroll.saturating_add(10),
+        (Popup::Links { selected, .. }, KeyCode::Up) => *selected = selected.saturating_sub(1),
+        (Popup::Links { urls, selected }, KeyCode::Down) => {
+            *selected = (*selected + 1).min(urls.len() - 1)
+        }
+        (Popup::Links { urls, selected }, KeyCode::Enter) => {
+            let url = urls[*selected].clone();
+            open_link(app, &url);
+        }
+        (Popup::Links { urls, .. }, KeyCode::Char(c @ '1'..='9')) => {
+            match urls.get(c as usize - '1' as usize) {
+                Some(url) => {
+                    let url = url.clone();
+                    open_link(app, &url);
+                }
+                None => return false,
+            }
+        }
+        (Popup::Links { urls, selected }, KeyCode::Char('c')) => {
+            let url = urls[*selected].clone();
+            app.popup = None;
+            copy(app, &url, &url);
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Copy `text` to the clipboard, naming it `what` in the notice.
+fn copy(app: &mut AppContext, text: &str, what: &str) {
+    match clipboard::copy(text) {
+        Ok(()) => {
+            app.notice = Some(Notice {
+                severity: Severity::Info,
+                content: format!("Copied {}", what),
+                until: Instant::now() + NOTICE_TIMEOUT,
+            })
+        }
+        Err(err) => log_system(
+            app,
+            SystemMessage::new(Severity::Error, None, format!("Couldn't copy {}", what))
+                .detail(err),
+        ),
+    }
+}
+
+/// Start moving a selection over the messages, from the newest one in view.
+fn start_selecting(app: &mut AppContext) {
+    let buffer = app.buffer_mut(app.tab);
+    if buffer.selected.is_none() && !buffer.messages.is_empty() {
+        buffer.selected = Some(buffer.scroll);
+    }
+    // Leaves selection mode when there's nothing left to select
+    app.selecting = buffer.selected.is_some();
+}
+
+fn stop_selecting(app: &mut AppContext) {
+    app.selecting = false;
+    app.buffer_mut(app.tab).selected = None;
+}
+
+/// Handle a key press in selection mode, returning whether anything changed.
+fn handle_selection_key(app: &mut AppContext, code: KeyCode) -> bool {
+    let buffer = app.buffer(app.tab);
+    // Nothing is selected yet after switching tabs or clearing the buffer
+    let message = match buffer
+        .selected
+        .and_then(|selected| buffer.messages.iter().rev().nth(selected))
+    {
+        Some(message) => message.message().clone(),
+        None => {
+            start_selecting(app);
+            return true;
+        }
+    };
+    match code {
+        KeyCode::Esc | KeyCode::Char('q') => stop_selecting(app),
+        KeyCode::Up | KeyCode::Char('k') => move_selection(app, true),
+        KeyCode::Down | KeyCode::Char('j') => move_selection(app, false),
+        KeyCode::Char('c') => copy(app, message.content(), "the message's content"),
+        KeyCode::Char('a') => match message.author() {
+            Some(author) => copy(app, author, "the message's author"),
+            None => return false,
         },
-        Err(err) => messages.lock().unwrap().push((
-            PilferMessage::System(SystemMessage {
-                content: format!("System: Couldn't send message: {:?}", err),
-            }),
-            Style::default().fg(Color::Red),
-        )),
+        KeyCode::Char('y') => copy(app, &message.to_string(), "the message"),
+        KeyCode::Char('r') => {
+            quote_reply(app, &message);
+            stop_selecting(app);
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Move the selection to an older or newer message, scrolling to keep it in view.
+fn move_selection(app: &mut AppContext, older: bool) {
+    // The oldest message shown in the last frame
+    let top = app.screen.rows.last().copied();
+    let buffer = app.buffer_mut(app.tab);
+    let selected = match buffer.selected {
+        Some(selected) if older => (selected + 1).min(buffer.messages.len().saturating_sub(1)),
+        Some(selected) => selected.saturating_sub(1),
+        None => return,
     };
+    buffer.selected = Some(selected);
+    if selected < buffer.scroll {
+        let amount = buffer.scroll - selected;
+        scroll_down(app, amount);
+    } else if let Some(top) = top.filter(|top| selected > *top) {
+        scroll_up(app, selected - top);
+    }
+}
+
+/// Quote a message in the input, mentioning its author.
+fn quote_reply(app: &mut AppContext, message: &PilferMessage) {
+    let mut reply: String = message
+        .content()
+        .lines()
+        .map(|line| format!("> {}\n", line))
+        .collect();
+    if let Some(author) = message.author() {
+        reply.push_str(&format!("@{} ", author));
+    }
+    app.input.insert_str(&reply);
+}
+
+/// When the UI next needs to be redrawn on its own, if ever.
+fn next_tick(app: &AppContext) -> Option<Instant> {
+    app.instances
+        .iter()
+        .filter_map(|instance| instance.next_tick())
+        .chain(app.notice.as_ref().map(|notice| notice.until))
+        .min()
+}
+
+/// Queue an over-long input as several messages which fit the instance's limit.
+fn split_input(app: &mut AppContext) {
+    if app.input.len() <= app.instance().info.message_limit {
+        return;
+    }
+    let input = app.input.take();
+    app.instance_mut().queue_split(&input);
+}
+
+/// Show the URLs in the newest messages of the active tab to pick one to open or copy.
+fn open_link_picker(app: &mut AppContext) {
+    let buffer = app.buffer(app.tab);
+    let mut urls: Vec<String> = vec![];
+    for m in buffer
+        .messages
+        .iter()
+        .rev()
+        .skip(buffer.scroll)
+        .take(LINK_PICKER_MESSAGES)
+    {
+        let text = m.message().to_string();
+        for range in find_urls(&text) {
+            if !urls.iter().any(|url| *url == text[range.clone()]) {
+                urls.push(text[range].to_string());
+            }
+        }
+    }
+    if urls.is_empty() {
+        app.notice = Some(Notice {
+            severity: Severity::Info,
+            content: "No links in the recent messages".to_string(),
+            until: Instant::now() + NOTICE_TIMEOUT,
+        });
+    } else {
+        app.popup = Some(Popup::Links { urls, selected: 0 });
+    }
+}
+
+/// Open `url` in the browser, closing the link picker if it's open.
+fn open_link(app: &mut AppContext, url: &str) {
+    app.popup = None;
+    if let Err(err) = open_url(url) {
+        log_system(
+            app,
+            SystemMessage::new(Severity::Error, None, "Couldn't open the link").detail(err),
+        );
+    }
+}
+
+/// Edit the input in `$VISUAL` or `$EDITOR`, handing the terminal over to it while it runs.
+async fn compose_in_editor<B: Backend + Write>(
+    terminal: &mut Terminal<B>,
+    app: &mut AppContext,
+) -> io::Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+    leave_terminal(terminal.backend_mut())?;
+    // Waiting for the editor blocks, which shouldn't hold up the gateways' tasks
+    let draft = app.input.text().to_string();
+    let command = editor.clone();
+    let result = task::spawn_blocking(move || run_editor(&command, &draft))
+        .await
+        .unwrap_or_else(|err| Err(io::Error::other(err)));
+    enter_terminal(terminal.backend_mut(), app.mouse)?;
+    // The editor drew all over the screen
+    terminal.clear()?;
+    app.previews.invalidate();
+    match result {
+        Ok(text) => app.input.set(text),
+        Err(err) => log_system(
+            app,
+            SystemMessage::new(
+                Severity::Error,
+                None,
+                format!("Couldn't edit the message with {}", editor),
+            )
+            .detail(err),
+        ),
+    }
+    Ok(())
+}
+
+/// Open `draft` in `editor` through a temporary file, returning the edited text.
+fn run_editor(editor: &str, draft: &str) -> io::Result<String> {
+    let path = env::temp_dir().join(format!(
+        "pilfer-{}-{:08x}.txt",
+        process::id(),
+        rand::random::<u32>()
+    ));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    let result = (|| {
+        file.write_all(draft.as_bytes())?;
+        drop(file);
+        // Editors like `code --wait` come with arguments
+        let mut command = editor.split_whitespace();
+        let status = Command::new(command.next().unwrap_or(DEFAULT_EDITOR))
+            .args(command)
+            .arg(&path)
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("it exited with {}", status)));
+        }
+        fs::read_to_string(&path)
+    })();
+    fs::remove_file(&path).ok();
+    // Editors like to end files with a newline
+    result.map(|text| text.strip_suffix('\n').unwrap_or(&text).to_string())
+}
+
+/// Remove the newest failed message and put its content back into the input.
+fn edit_failed(app: &mut AppContext) {
+    if let Some(content) = app.instance_mut().take_failed() {
+        if app.input.is_empty() {
+            app.input.set(content);
+        } else {
+            app.input.set(format!("{}\n{}", content, app.input.text()));
+        }
+    }
 }
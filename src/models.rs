@@ -1,13 +1,25 @@
 use std::fmt::Display;
 use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+#[cfg(target_os = "linux")]
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+use chrono::{DateTime, Local, Utc};
 #[cfg(target_os = "linux")]
 use notify_rust::NotificationHandle;
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use todel::models::{ErrorResponse, Message};
-use tui::style::Style;
+use todel::models::{ErrorResponse, FileData, Message};
+
+use crate::{
+    buffer::{Buffer, Tab},
+    input::Input,
+    instance::Instance,
+    keys::KeyBindings,
+    preview::{Preview, Previews},
+    theme::Theme,
+    ui::ScreenLayout,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(untagged)]
@@ -16,42 +28,410 @@ pub enum MessageResponse {
     Error(ErrorResponse),
 }
 
-// While in hindsight this might look like it's modeled in a bad way, you're right, it's modeled in
-// a bad way.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FileResponse {
+    Success(FileData),
+    Error(ErrorResponse),
+}
+
+/// A message from Pilfer itself rather than another user
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemMessage {
+    #[serde(default)]
+    pub severity: Severity,
+    /// When the message was created
+    #[serde(default)]
+    pub timestamp: DateTime<Utc>,
+    /// What the message is about, if it's about anything in particular
+    #[serde(default)]
+    pub source: Option<Source>,
     pub content: String,
+    /// Further information like the underlying error, only shown in the system log
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+impl SystemMessage {
+    pub fn new(severity: Severity, source: Option<Source>, content: impl Into<String>) -> Self {
+        Self {
+            severity,
+            timestamp: Utc::now(),
+            source,
+            content: content.into(),
+            detail: None,
+        }
+    }
+
+    pub fn detail(mut self, detail: impl Display) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+impl Display for SystemMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [{}] ",
+            self.timestamp.with_timezone(&Local).format("%H:%M:%S"),
+            self.severity
+        )?;
+        if let Some(source) = self.source {
+            write!(f, "{}: ", source)?;
+        }
+        write!(f, "{}", self.content)?;
+        if let Some(detail) = &self.detail {
+            write!(f, " ({})", detail)?;
+        }
+        Ok(())
+    }
 }
 
+/// How serious a system message is
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warn => write!(f, "warn"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// What a system message is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Source {
+    /// The connection to Pandemonium
+    Gateway,
+    /// Requests to Oprish
+    Http,
+    /// A command run by the user
+    Command,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Gateway => write!(f, "gateway"),
+            Source::Http => write!(f, "http"),
+            Source::Command => write!(f, "command"),
+        }
+    }
+}
+
+/// The newest system message, briefly shown below the chat
 #[derive(Debug)]
+pub struct Notice {
+    pub severity: Severity,
+    pub content: String,
+    /// When the notice stops being shown
+    pub until: Instant,
+}
+
+/// A message sent by us which hasn't necessarily made it to Oprish yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutgoingMessage {
+    /// Local id used to keep track of the message while sending it
+    pub id: u64,
+    pub author: String,
+    pub content: String,
+    pub state: OutgoingState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutgoingState {
+    /// Waiting for its turn to be sent
+    Queued,
+    /// The request to Oprish is in flight
+    Sending,
+    /// Oprish accepted the message but it hasn't come back through the gateway yet
+    Sent,
+    /// The message came back through the gateway
+    Delivered,
+    /// Oprish accepted the message but it never came back through the gateway
+    Unconfirmed,
+    /// Oprish didn't accept the message, with the reason why
+    Failed(String),
+}
+
+/// What a message means to the user, the theme decides how that looks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attributes {
+    /// The message mentions the user
+    pub mention: bool,
+    /// The message matches one of the user's highlight rules
+    pub highlight: bool,
+    /// The message was sent by the user
+    pub own: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PilferMessage {
     Eludris(Message),
     System(SystemMessage),
+    Outgoing(OutgoingMessage),
+}
+
+impl PilferMessage {
+    /// Who wrote the message, system messages have no author.
+    pub fn author(&self) -> Option<&str> {
+        match self {
+            PilferMessage::Eludris(msg) => Some(&msg.author),
+            PilferMessage::System(_) => None,
+            PilferMessage::Outgoing(msg) => Some(&msg.author),
+        }
+    }
+
+    pub fn content(&self) -> &str {
+        match self {
+            PilferMessage::Eludris(msg) => &msg.content,
+            PilferMessage::System(msg) => &msg.content,
+            PilferMessage::Outgoing(msg) => &msg.content,
+        }
+    }
 }
 
 impl Display for PilferMessage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             PilferMessage::Eludris(msg) => write!(f, "[{}]: {}", msg.author, msg.content),
-            PilferMessage::System(msg) => write!(f, "{}", msg.content),
+            PilferMessage::System(msg) => write!(f, "{}", msg),
+            PilferMessage::Outgoing(msg) => {
+                write!(f, "[{}]: {}", msg.author, msg.content)?;
+                match &msg.state {
+                    OutgoingState::Queued => write!(f, " (queued)"),
+                    OutgoingState::Sending => write!(f, " (sending…)"),
+                    OutgoingState::Sent | OutgoingState::Delivered => Ok(()),
+                    OutgoingState::Unconfirmed => write!(f, " (never arrived through the gateway)"),
+                    OutgoingState::Failed(reason) => {
+                        write!(f, " (failed: {}, Ctrl+R to retry, Ctrl+E to edit)", reason)
+                    }
+                }
+            }
         }
     }
 }
 
+/// A message we sent which should come back through the gateway
+#[derive(Debug)]
+pub struct PendingEcho {
+    /// The id of the message's [`OutgoingMessage`]
+    pub id: u64,
+    pub content: String,
+    /// When to give up waiting for it, set once Oprish accepted it
+    pub deadline: Option<Instant>,
+}
+
+/// A message waiting in the outbox
+#[derive(Debug)]
+pub struct QueuedMessage {
+    /// The id of the message's [`OutgoingMessage`]
+    pub id: u64,
+    pub content: String,
+}
+
+/// Events sent to the main loop by the background tasks
+#[derive(Debug)]
+pub enum AppEvent {
+    /// A new message to display
+    Message(PilferMessage),
+    /// A change in the gateway connection's state
+    Gateway(GatewayEvent),
+    /// A message finished sending
+    Sent {
+        message: QueuedMessage,
+        result: SendResult,
+    },
+    /// A file upload to Effis progressed
+    Upload(UploadEvent),
+    /// A preview of an image finished loading, `None` if the link isn't an image or too big
+    Preview {
+        url: String,
+        result: Result<Option<Preview>, String>,
+    },
+}
+
+#[derive(Debug)]
+pub enum UploadEvent {
+    /// How many bytes were sent so far
+    Progress(u64),
+    /// The upload finished, with the uploaded file or why it failed
+    Done(Result<FileData, String>),
+}
+
+/// A file being uploaded to Effis
+#[derive(Debug)]
+pub struct Upload {
+    pub name: String,
+    /// How many bytes were sent so far
+    pub sent: u64,
+    /// The file's size
+    pub total: u64,
+}
+
+#[derive(Debug)]
+pub enum SendResult {
+    /// Oprish accepted the message, returning it as it will be sent through the gateway
+    Sent(Message),
+    /// Oprish rate limited us, the message should be sent again after `try_after`
+    RateLimited { try_after: Duration },
+    /// The message couldn't be sent, with the reason why
+    Failed(String),
+}
+
+#[derive(Debug)]
+pub enum GatewayEvent {
+    /// The Hello payload was received
+    Connected,
+    /// The connection was closed, a reconnect will be attempted
+    Disconnected,
+    /// A Pong payload was received, `latency` is the time since the last Ping if known
+    Pong { latency: Option<Duration> },
+    /// The gateway rate limited us
+    RateLimited { until: Instant },
+    /// A frame which is valid but isn't a payload Pilfer can handle
+    UnknownFrame(String),
+    /// A frame which couldn't be decoded at all
+    UndecodableFrame(String),
+}
+
+/// Gateway connection state and statistics, shown in the debug view
+#[derive(Debug, Default)]
+pub struct GatewayStats {
+    pub connected: bool,
+    /// Until when the gateway is rate limiting us
+    pub rate_limited_until: Option<Instant>,
+    /// The latency measured from the last Ping-Pong
+    pub latency: Option<Duration>,
+    pub pongs: u64,
+    pub rate_limits: u64,
+    pub reconnects: u64,
+    pub unknown_frames: u64,
+    pub undecodable_frames: u64,
+    /// The reason the last unknown or undecodable frame was rejected
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Popup {
+    /// The instance's info, scrolled down by `scroll` lines
+    Info { scroll: u16 },
+    /// The URLs in the newest messages, newest first
+    Links { urls: Vec<String>, selected: usize },
+}
+
 pub struct AppContext {
     /// Current input
-    pub input: String,
+    pub input: Input,
     /// User name
     pub name: String,
-    /// Received messages
-    pub messages: Arc<Mutex<Vec<(PilferMessage, Style)>>>,
-    /// Reqwest HttpClient
-    pub http_client: Client,
-    /// Oprish URL
-    pub rest_url: String,
+    /// Connected instances
+    pub instances: Vec<Instance>,
+    /// The index of the instance messages get sent to, the last one shown
+    pub current: usize,
+    /// The tab currently shown
+    pub tab: Tab,
+    /// Messages mentioning the user
+    pub mentions: Buffer,
+    /// Results of the last search
+    pub search: Buffer,
+    /// What was searched for last
+    pub search_query: Option<String>,
+    /// System messages from every instance
+    pub system: Buffer,
+    /// The newest system message while it's shown
+    pub notice: Option<Notice>,
+    /// The styles messages are rendered with
+    pub theme: Theme,
+    /// Whether links are emitted as OSC 8 hyperlinks
+    pub hyperlinks: bool,
+    /// Whether keys move the selection through the messages rather than edit the input
+    pub selecting: bool,
+    /// Whether a large paste is in the input, so pressing Enter warns about it instead of sending
+    pub warn_before_send: bool,
+    /// Which keys send the input and which insert newlines
+    pub keys: KeyBindings,
+    /// Whether mouse events are captured, which stops the terminal's own text selection
+    pub mouse: bool,
+    /// Where everything was drawn in the last frame
+    pub screen: ScreenLayout,
+    /// Inline previews of images linked to in messages
+    pub previews: Previews,
+    /// Whether the gateway debug view is shown
+    pub show_debug: bool,
+    /// The popup currently shown over the messages, if any
+    pub popup: Option<Popup>,
     /// Whether the user is currently focused.
     pub focused: Arc<AtomicBool>,
     /// The notification
     #[cfg(target_os = "linux")]
     pub notification: Arc<Mutex<Option<NotificationHandle>>>,
 }
+
+impl AppContext {
+    /// The instance currently shown.
+    pub fn instance(&self) -> &Instance {
+        &self.instances[self.current]
+    }
+
+    pub fn instance_mut(&mut self) -> &mut Instance {
+        &mut self.instances[self.current]
+    }
+
+    /// Every tab in the order they're shown in.
+    pub fn tabs(&self) -> Vec<Tab> {
+        (0..self.instances.len())
+            .map(Tab::Instance)
+            .chain([Tab::Mentions, Tab::Search, Tab::System])
+            .collect()
+    }
+
+    pub fn buffer(&self, tab: Tab) -> &Buffer {
+        match tab {
+            Tab::Instance(index) => &self.instances[index].buffer,
+            Tab::Mentions => &self.mentions,
+            Tab::Search => &self.search,
+            Tab::System => &self.system,
+        }
+    }
+
+    pub fn buffer_mut(&mut self, tab: Tab) -> &mut Buffer {
+        match tab {
+            Tab::Instance(index) => &mut self.instances[index].buffer,
+            Tab::Mentions => &mut self.mentions,
+            Tab::Search => &mut self.search,
+            Tab::System => &mut self.system,
+        }
+    }
+
+    /// The buffer shown in a tab along with the image previews, which are needed at the same
+    /// time to render it.
+    pub fn buffer_and_previews(&mut self, tab: Tab) -> (&mut Buffer, &Previews) {
+        let buffer = match tab {
+            Tab::Instance(index) => &mut self.instances[index].buffer,
+            Tab::Mentions => &mut self.mentions,
+            Tab::Search => &mut self.search,
+            Tab::System => &mut self.system,
+        };
+        (buffer, &self.previews)
+    }
+
+    pub fn select_tab(&mut self, tab: Tab) {
+        self.tab = tab;
+        match tab {
+            Tab::Instance(index) => self.current = index,
+            Tab::System => self.notice = None,
+            _ => {}
+        }
+        self.buffer_mut(tab).mark_read();
+    }
+}
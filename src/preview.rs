@@ -0,0 +1,413 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fmt::Write as _,
+    io::{self, Write},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crossterm::{
+    cursor::{MoveTo, RestorePosition, SavePosition},
+    queue,
+    style::Print,
+};
+use image::RgbaImage;
+use reqwest::{header::CONTENT_TYPE, Client};
+use tokio::{sync::mpsc::UnboundedSender, task};
+use tui::{
+    style::{Color, Style},
+    text::{Span, Spans},
+};
+
+use crate::models::AppEvent;
+
+/// The biggest image which gets downloaded for a preview, in bytes
+pub const MAX_PREVIEW_SIZE: u64 = 5_000_000;
+/// How many columns a preview takes up at most
+pub const PREVIEW_COLUMNS: u32 = 40;
+/// How many rows a preview takes up at most
+pub const PREVIEW_ROWS: u32 = 10;
+/// The assumed size of a terminal cell in pixels, as there's no portable way to ask for it
+const CELL_PIXELS: (u32, u32) = (10, 20);
+
+/// How images are drawn in the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    /// Two pixels per cell using `▀` with different foreground and background colours, which
+    /// works in any terminal with true colour support
+    HalfBlocks,
+}
+
+impl Protocol {
+    /// The names `PILFER_PREVIEWS` takes to turn previews on
+    pub const NAMES: [&'static str; 4] = ["auto", "kitty", "sixel", "blocks"];
+
+    pub fn by_name(name: &str) -> Option<Protocol> {
+        match name.to_lowercase().as_str() {
+            "auto" => Some(Protocol::detect()),
+            "kitty" => Some(Protocol::Kitty),
+            "sixel" => Some(Protocol::Sixel),
+            "blocks" => Some(Protocol::HalfBlocks),
+            _ => None,
+        }
+    }
+
+    /// Guess what the terminal supports from the environment it set up.
+    pub fn detect() -> Protocol {
+        let term = env::var("TERM").unwrap_or_default();
+        let program = env::var("TERM_PROGRAM").unwrap_or_default();
+        if env::var_os("KITTY_WINDOW_ID").is_some()
+            || term.contains("kitty")
+            || matches!(program.as_str(), "WezTerm" | "ghostty")
+        {
+            Protocol::Kitty
+        } else if term.contains("foot") || term.contains("mlterm") || program == "iTerm.app" {
+            Protocol::Sixel
+        } else {
+            Protocol::HalfBlocks
+        }
+    }
+}
+
+/// A downscaled image ready to be drawn below a message
+#[derive(Debug)]
+pub struct Preview {
+    pub columns: u16,
+    pub rows: u16,
+    image: Image,
+}
+
+#[derive(Debug)]
+enum Image {
+    Blocks(RgbaImage),
+    Kitty {
+        id: u32,
+        /// The escape sequences uploading the image, only sent before it's first shown
+        transmit: String,
+        transmitted: bool,
+    },
+    Sixel(String),
+}
+
+impl Preview {
+    /// The rows taken up by the preview in the message list.
+    ///
+    /// Kitty and sixel images are drawn on top of empty rows after the frame is rendered.
+    pub fn lines(&self) -> Vec<Spans<'static>> {
+        let image = match &self.image {
+            Image::Blocks(image) => image,
+            _ => return vec![Spans::default(); self.rows as usize],
+        };
+        let colour = |x, y| {
+            if y >= image.height() {
+                return Color::Reset;
+            }
+            match image.get_pixel(x, y).0 {
+                [_, _, _, a] if a < 128 => Color::Reset,
+                [r, g, b, _] => Color::Rgb(r, g, b),
+            }
+        };
+        (0..self.rows as u32)
+            .map(|row| {
+                Spans::from(
+                    (0..image.width())
+                        .map(|x| {
+                            Span::styled(
+                                "▀",
+                                Style::default()
+                                    .fg(colour(x, row * 2))
+                                    .bg(colour(x, row * 2 + 1)),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+enum PreviewState {
+    Loading,
+    Ready(Preview),
+    /// Not an image, too big, or broken, so it isn't tried again
+    Unavailable,
+}
+
+/// Where a preview was drawn on the screen
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    pub url: String,
+    pub x: u16,
+    pub y: u16,
+}
+
+/// Image previews of Effis links, fetched in the background
+#[derive(Debug)]
+pub struct Previews {
+    /// How previews are drawn, `None` if they're turned off
+    pub protocol: Option<Protocol>,
+    images: HashMap<String, PreviewState>,
+    next_id: u32,
+    /// Where previews go in the frame currently being drawn
+    pub placements: Vec<Placement>,
+    /// Where previews were drawn on the screen, so they're only redrawn when they move
+    drawn: Vec<Placement>,
+}
+
+impl Previews {
+    pub fn new(protocol: Option<Protocol>) -> Self {
+        Self {
+            protocol,
+            images: HashMap::new(),
+            next_id: 1,
+            placements: vec![],
+            drawn: vec![],
+        }
+    }
+
+    /// Start fetching a preview of `url` unless it was already.
+    pub fn request(
+        &mut self,
+        url: &str,
+        http_client: &Client,
+        instance: usize,
+        events: &UnboundedSender<(usize, AppEvent)>,
+    ) {
+        let protocol = match self.protocol {
+            Some(protocol) if !self.images.contains_key(url) => protocol,
+            _ => return,
+        };
+        self.images.insert(url.to_string(), PreviewState::Loading);
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let http_client = http_client.clone();
+        let url = url.to_string();
+        let events = events.clone();
+        tokio::spawn(async move {
+            let result = fetch_preview(&http_client, &url, protocol, id).await;
+            events
+                .send((instance, AppEvent::Preview { url, result }))
+                .ok();
+        });
+    }
+
+    pub fn finish(&mut self, url: String, result: Result<Option<Preview>, String>) {
+        let state = match result {
+            Ok(Some(preview)) => PreviewState::Ready(preview),
+            _ => PreviewState::Unavailable,
+        };
+        self.images.insert(url, state);
+    }
+
+    pub fn get(&self, url: &str) -> Option<&Preview> {
+        match self.images.get(url) {
+            Some(PreviewState::Ready(preview)) => Some(preview),
+            _ => None,
+        }
+    }
+
+    /// Forget where previews were drawn, for when the screen got cleared.
+    pub fn invalidate(&mut self) {
+        self.drawn.clear();
+    }
+
+    /// Whether the screen has to be cleared before drawing previews.
+    ///
+    /// Sixel images stay on the screen until something is drawn over them, which doesn't happen
+    /// to the empty rows they were on.
+    pub fn needs_clear(&self) -> bool {
+        self.protocol == Some(Protocol::Sixel)
+            && !self.drawn.is_empty()
+            && self.placements != self.drawn
+    }
+
+    /// Draw the kitty and sixel previews placed in the last frame if they moved.
+    pub fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.placements == self.drawn {
+            return Ok(());
+        }
+        if self.protocol == Some(Protocol::Kitty) {
+            // Only removes the placements, the images stay uploaded
+            queue!(out, Print("\x1b_Ga=d,d=a,q=2\x1b\\"))?;
+        }
+        for placement in &self.placements {
+            let preview = match self.images.get_mut(&placement.url) {
+                Some(PreviewState::Ready(preview)) => preview,
+                _ => continue,
+            };
+            queue!(out, SavePosition, MoveTo(placement.x, placement.y))?;
+            match &mut preview.image {
+                Image::Blocks(_) => {}
+                Image::Kitty {
+                    id,
+                    transmit,
+                    transmitted,
+                } => {
+                    if !*transmitted {
+                        queue!(out, Print(&transmit))?;
+                        *transmitted = true;
+                    }
+                    queue!(
+                        out,
+                        Print(format!(
+                            "\x1b_Ga=p,i={},c={},r={},C=1,q=2\x1b\\",
+                            id, preview.columns, preview.rows
+                        ))
+                    )?;
+                }
+                Image::Sixel(data) => queue!(out, Print(&data))?,
+            }
+            queue!(out, RestorePosition)?;
+        }
+        out.flush()?;
+        self.drawn = self.placements.clone();
+        Ok(())
+    }
+}
+
+async fn fetch_preview(
+    http_client: &Client,
+    url: &str,
+    protocol: Protocol,
+    id: u32,
+) -> Result<Option<Preview>, String> {
+    let mut res = http_client
+        .get(url)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|err| err.to_string())?;
+    let is_image = matches!(
+        res.headers().get(CONTENT_TYPE).and_then(|value| value.to_str().ok()),
+        Some(content_type) if content_type.starts_with("image/")
+    );
+    if !is_image || matches!(res.content_length(), Some(length) if length > MAX_PREVIEW_SIZE) {
+        return Ok(None);
+    }
+    let mut data = vec![];
+    while let Some(chunk) = res.chunk().await.map_err(|err| err.to_string())? {
+        data.extend_from_slice(&chunk);
+        // The length isn't always known up front
+        if data.len() as u64 > MAX_PREVIEW_SIZE {
+            return Ok(None);
+        }
+    }
+    // Decoding and encoding big images takes a while, keep it away from the async runtime
+    task::spawn_blocking(move || decode(&data, protocol, id))
+        .await
+        .map_err(|err| err.to_string())?
+}
+
+fn decode(data: &[u8], protocol: Protocol, id: u32) -> Result<Option<Preview>, String> {
+    let image = image::load_from_memory(data).map_err(|err| err.to_string())?;
+    let (cell_width, cell_height) = match protocol {
+        Protocol::HalfBlocks => (1, 2),
+        Protocol::Kitty | Protocol::Sixel => CELL_PIXELS,
+    };
+    let image = image
+        .thumbnail(PREVIEW_COLUMNS * cell_width, PREVIEW_ROWS * cell_height)
+        .to_rgba8();
+    if image.width() == 0 || image.height() == 0 {
+        return Ok(None);
+    }
+    let columns = image.width().div_ceil(cell_width) as u16;
+    let rows = image.height().div_ceil(cell_height) as u16;
+    let image = match protocol {
+        Protocol::HalfBlocks => Image::Blocks(image),
+        Protocol::Kitty => Image::Kitty {
+            id,
+            transmit: kitty_transmit(&image, id),
+            transmitted: false,
+        },
+        Protocol::Sixel => Image::Sixel(sixel(&image)),
+    };
+    Ok(Some(Preview {
+        columns,
+        rows,
+        image,
+    }))
+}
+
+/// Upload raw RGBA pixels to kitty under `id` without showing them yet.
+fn kitty_transmit(image: &RgbaImage, id: u32) -> String {
+    let data = STANDARD.encode(image.as_raw());
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            write!(
+                out,
+                "\x1b_Ga=t,f=32,i={},s={},v={},q=2,m={};",
+                id,
+                image.width(),
+                image.height(),
+                more
+            )
+            .ok();
+        } else {
+            write!(out, "\x1b_Gm={};", more).ok();
+        }
+        // Base64 is always ASCII
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push_str("\x1b\\");
+    }
+    out
+}
+
+/// Encode an image as sixels, using a 6×6×6 colour cube as the palette.
+fn sixel(image: &RgbaImage) -> String {
+    let (width, height) = (image.width(), image.height());
+    // The 1 keeps transparent pixels showing the background
+    let mut out = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for i in 0..216 {
+        write!(
+            out,
+            "#{};2;{};{};{}",
+            i,
+            i / 36 * 20,
+            i / 6 % 6 * 20,
+            i % 6 * 20
+        )
+        .ok();
+    }
+    let level = |c: u8| (c as usize * 5 + 127) / 255;
+    for band in (0..height).step_by(6) {
+        // The sixels of every column for each colour used in this band
+        let mut colours: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        for x in 0..width {
+            for dy in 0..(height - band).min(6) {
+                let [r, g, b, a] = image.get_pixel(x, band + dy).0;
+                if a < 128 {
+                    continue;
+                }
+                colours
+                    .entry(level(r) * 36 + level(g) * 6 + level(b))
+                    .or_insert_with(|| vec![0; width as usize])[x as usize] |= 1 << dy;
+            }
+        }
+        for (colour, sixels) in colours {
+            write!(out, "#{}", colour).ok();
+            let mut x = 0;
+            while x < sixels.len() {
+                let run = sixels[x..].iter().take_while(|s| **s == sixels[x]).count();
+                let c = (63 + sixels[x]) as char;
+                if run > 3 {
+                    write!(out, "!{}{}", run, c).ok();
+                } else {
+                    out.extend(std::iter::repeat_n(c, run));
+                }
+                x += run;
+            }
+            // Back to the start of the band for the next colour
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
@@ -0,0 +1,118 @@
+use std::time::{Duration, Instant};
+
+use todel::models::RateLimitConf;
+
+/// Client side tracking of one of the instance's rate limit buckets.
+///
+/// The instance's advertised configuration is used to avoid hitting the limit in the first place,
+/// while `try_after` responses from the server always take precedence.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    /// The bucket's window length and how many requests fit in it, if known
+    conf: Option<(Duration, u32)>,
+    /// When the current window started
+    window_start: Option<Instant>,
+    /// How many requests were sent in the current window
+    sent: u32,
+    /// Until when the server told us to stop sending requests
+    blocked_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(conf: Option<&RateLimitConf>) -> Self {
+        Self {
+            conf: conf.map(|conf| (Duration::from_secs(conf.reset_after as u64), conf.limit)),
+            ..Default::default()
+        }
+    }
+
+    /// When the next request can be sent, `None` if it can be sent right away.
+    pub fn wait_until(&self, now: Instant) -> Option<Instant> {
+        if let Some(until) = self.blocked_until.filter(|until| *until > now) {
+            return Some(until);
+        }
+        match (self.conf, self.window_start) {
+            (Some((reset_after, limit)), Some(start)) if self.sent >= limit => {
+                Some(start + reset_after).filter(|reset| *reset > now)
+            }
+            _ => None,
+        }
+    }
+
+    /// Record a request being sent.
+    pub fn record(&mut self, now: Instant) {
+        match (self.conf, self.window_start) {
+            (Some((reset_after, _)), Some(start)) if now < start + reset_after => self.sent += 1,
+            _ => {
+                self.window_start = Some(now);
+                self.sent = 1;
+            }
+        }
+    }
+
+    /// Record the server rate limiting us for `try_after`.
+    pub fn limited(&mut self, now: Instant, try_after: Duration) {
+        self.blocked_until = Some(now + try_after);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(reset_after: u64, limit: u32) -> RateLimiter {
+        RateLimiter {
+            conf: Some((Duration::from_secs(reset_after), limit)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn waits_once_window_is_full() {
+        let now = Instant::now();
+        let mut limiter = limiter(10, 2);
+        limiter.record(now);
+        assert_eq!(limiter.wait_until(now), None);
+        limiter.record(now + Duration::from_secs(1));
+        assert_eq!(
+            limiter.wait_until(now + Duration::from_secs(2)),
+            Some(now + Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn resets_after_window() {
+        let now = Instant::now();
+        let mut limiter = limiter(10, 2);
+        limiter.record(now);
+        limiter.record(now);
+        let later = now + Duration::from_secs(10);
+        assert_eq!(limiter.wait_until(later), None);
+        // A new window starts with the next request
+        limiter.record(later);
+        assert_eq!(limiter.wait_until(later), None);
+        limiter.record(later);
+        assert_eq!(
+            limiter.wait_until(later),
+            Some(later + Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn try_after_takes_precedence() {
+        let now = Instant::now();
+        let mut limiter = limiter(10, 5);
+        limiter.record(now);
+        limiter.limited(now, Duration::from_secs(3));
+        assert_eq!(
+            limiter.wait_until(now + Duration::from_secs(1)),
+            Some(now + Duration::from_secs(3))
+        );
+        assert_eq!(limiter.wait_until(now + Duration::from_secs(3)), None);
+
+        // Even without knowing the instance's configuration
+        let mut limiter = RateLimiter::default();
+        limiter.limited(now, Duration::from_secs(3));
+        assert_eq!(limiter.wait_until(now), Some(now + Duration::from_secs(3)));
+    }
+}
@@ -0,0 +1,173 @@
+/// Separators to split messages at, most preferred first
+const SEPARATORS: [&str; 3] = ["\n\n", "\n", " "];
+const FENCE: &str = "```";
+const CLOSE: &str = "\n```";
+
+/// Split `content` into parts no longer than `limit` bytes.
+///
+/// Parts are split at paragraph, line or word boundaries in that order of preference, avoiding
+/// splitting inside code blocks unless there's no other way. Code blocks which do get split are
+/// closed at the end of the part and reopened at the start of the next one.
+pub fn split_message(content: &str, limit: usize) -> Vec<String> {
+    let mut parts = vec![];
+    let mut remaining = content.trim();
+    // The line opening the code block `remaining` starts inside of
+    let mut fence: Option<&str> = None;
+    loop {
+        let reopen = fence.map_or(0, |fence| fence.len() + 1);
+        if remaining.is_empty() || reopen + remaining.len() <= limit {
+            break;
+        }
+        let close = if fence.is_some()
+            || remaining[..floor_char_boundary(remaining, limit)].contains(FENCE)
+        {
+            CLOSE.len()
+        } else {
+            0
+        };
+        let window =
+            &remaining[..floor_char_boundary(remaining, limit.saturating_sub(reopen + close))];
+        let (end, next) = find_break(window, fence.is_some(), true)
+            .or_else(|| find_break(window, fence.is_some(), false))
+            .unwrap_or((window.len(), window.len()));
+        // Always make progress, even if the limit is smaller than a single character
+        let (end, next) = if next == 0 {
+            let len = remaining.chars().next().map_or(0, char::len_utf8);
+            (len, len)
+        } else {
+            (end, next)
+        };
+        // Code blocks are only carried over if that leaves room for some of their content
+        let next_fence = track_fence(remaining, next, fence)
+            .filter(|fence| fence.len() + 1 + CLOSE.len() <= limit / 2);
+        // Only the separator and blank lines are dropped so indentation survives
+        let body = remaining[..end].trim_end_matches('\n');
+        if !body.trim().is_empty() {
+            let mut part = String::with_capacity(limit);
+            if let Some(fence) = fence {
+                part.push_str(fence);
+                part.push('\n');
+            }
+            part.push_str(body);
+            if next_fence.is_some() {
+                part.push_str(CLOSE);
+            }
+            parts.push(part);
+        }
+        fence = next_fence;
+        remaining = remaining[next..].trim_start_matches('\n');
+    }
+    if !remaining.trim().is_empty() {
+        match fence {
+            Some(fence) => parts.push(format!("{}\n{}", fence, remaining)),
+            None => parts.push(remaining.to_string()),
+        }
+    }
+    parts
+}
+
+/// Follow the fences in the first `end` bytes of `text`, returning the line opening the code block
+/// they end inside of.
+fn track_fence<'a>(text: &'a str, end: usize, mut fence: Option<&'a str>) -> Option<&'a str> {
+    for (index, _) in text[..end].match_indices(FENCE) {
+        fence = match fence {
+            Some(_) => None,
+            None => {
+                // Keep the language so highlighting carries over
+                let line = text[index..].split('\n').next().unwrap_or(FENCE);
+                let info = &line[FENCE.len()..];
+                let starts_line = text[..index].ends_with('\n') || index == 0;
+                if starts_line
+                    && info.chars().all(|c| {
+                        c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '#' | '.' | '_')
+                    })
+                {
+                    Some(line)
+                } else {
+                    Some(FENCE)
+                }
+            }
+        };
+    }
+    fence
+}
+
+/// Find the best place to split `window` at, returning where the part ends and where the next one
+/// starts.
+fn find_break(window: &str, in_fence: bool, outside_fence: bool) -> Option<(usize, usize)> {
+    SEPARATORS.iter().find_map(|separator| {
+        window
+            .rmatch_indices(separator)
+            .map(|(index, _)| index)
+            .filter(|index| *index > 0)
+            .find(|index| {
+                !outside_fence || in_fence == (window[..*index].matches(FENCE).count() % 2 == 1)
+            })
+            .map(|index| (index, index + separator.len()))
+    })
+}
+
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    (0..=index.min(text.len()))
+        .rev()
+        .find(|i| text.is_char_boundary(*i))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(content: &str, limit: usize) -> Vec<String> {
+        let parts = split_message(content, limit);
+        for part in &parts {
+            assert!(part.len() <= limit, "{:?} is over {} bytes", part, limit);
+        }
+        parts
+    }
+
+    #[test]
+    fn prefers_paragraphs() {
+        assert_eq!(split("aaaa\n\nbbbb\ncccc", 12), ["aaaa", "bbbb\ncccc"]);
+    }
+
+    #[test]
+    fn prefers_lines_over_words() {
+        assert_eq!(split("aa bb\ncc dd", 8), ["aa bb", "cc dd"]);
+    }
+
+    #[test]
+    fn falls_back_to_words() {
+        assert_eq!(split("aaa bbb ccc", 8), ["aaa bbb", "ccc"]);
+    }
+
+    #[test]
+    fn keeps_indentation() {
+        assert_eq!(
+            split("fn main() {\n    let x = 1;\n}", 20),
+            ["fn main() {", "    let x = 1;\n}"]
+        );
+    }
+
+    #[test]
+    fn avoids_splitting_code_blocks() {
+        assert_eq!(
+            split("intro text\n```\ncode line\n```", 20),
+            ["intro text", "```\ncode line\n```"]
+        );
+    }
+
+    #[test]
+    fn reopens_split_code_blocks() {
+        assert_eq!(
+            split("```rust\nline one\nline two\n```", 24),
+            ["```rust\nline one\n```", "```rust\nline two\n```"]
+        );
+    }
+
+    #[test]
+    fn splits_multibyte_characters_whole() {
+        assert_eq!(split("日本語日本語", 7), ["日本", "語日", "本語"]);
+        assert_eq!(split("ééééé", 3), ["é"; 5]);
+    }
+}
@@ -0,0 +1,382 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    models::{Attributes, PilferMessage},
+    ui::{wrap, Line},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredMessage {
+    message: PilferMessage,
+    #[serde(default)]
+    pub attributes: Attributes,
+    /// The message's lines wrapped to the width they were last rendered at
+    #[serde(skip)]
+    layout: Option<(u16, Vec<Line>)>,
+}
+
+impl StoredMessage {
+    pub fn new(message: PilferMessage, attributes: Attributes) -> Self {
+        Self {
+            message,
+            attributes,
+            layout: None,
+        }
+    }
+
+    pub fn message(&self) -> &PilferMessage {
+        &self.message
+    }
+
+    /// Get the message mutably, its layout is recomputed on the next render.
+    pub fn message_mut(&mut self) -> &mut PilferMessage {
+        self.layout = None;
+        &mut self.message
+    }
+
+    /// Get the message's lines wrapped to `width`, reusing the previous layout if the width
+    /// didn't change.
+    ///
+    /// Styles aren't part of the layout so changing the theme doesn't need to invalidate it.
+    pub fn lines(&mut self, width: u16) -> &[Line] {
+        match self.layout {
+            Some((cached_width, _)) if cached_width == width => {}
+            _ => self.layout = Some((width, wrap(&self.message.to_string(), width as usize))),
+        }
+        &self.layout.as_ref().unwrap().1
+    }
+}
+
+/// A bounded message buffer.
+///
+/// Only the newest `limit` messages are kept in memory, older ones are dropped or appended to the
+/// on-disk log if one is configured, from where they can be paged back in when scrolling up.
+///
+/// At most `limit` messages are paged in at once too. Once scrolled further back than that the
+/// newest paged messages are dropped again, leaving a gap between them and the in-memory ones
+/// which gets paged back in when scrolling down.
+#[derive(Debug)]
+pub struct MessageStore {
+    /// The newest messages, oldest first
+    messages: VecDeque<StoredMessage>,
+    /// Older messages paged back in from the log, oldest first
+    paged: VecDeque<StoredMessage>,
+    /// Maximum amount of messages kept in each of the buffers
+    limit: usize,
+    log: Option<MessageLog>,
+}
+
+#[derive(Debug)]
+struct MessageLog {
+    path: PathBuf,
+    file: File,
+    /// The byte offset of every message written to the log in this session
+    offsets: Vec<u64>,
+    /// Index of the first offset which wasn't cleared by the user
+    floor: usize,
+    /// Index of the offset the first paged message was read from
+    paged_from: usize,
+}
+
+/// How the paged messages changed after paging some in
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Paged {
+    /// How many messages were read from the log
+    pub loaded: usize,
+    /// How many of the previously paged messages were dropped to make room for them
+    pub dropped: usize,
+}
+
+impl MessageStore {
+    pub fn new(limit: usize, log_path: Option<&Path>) -> io::Result<Self> {
+        let log = match log_path {
+            Some(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Some(MessageLog {
+                    path: path.to_path_buf(),
+                    file,
+                    offsets: vec![],
+                    floor: 0,
+                    paged_from: 0,
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            messages: VecDeque::with_capacity(limit.min(1024)),
+            paged: VecDeque::new(),
+            limit,
+            log,
+        })
+    }
+
+    /// Add a new message, evicting the oldest one if the scrollback is full.
+    ///
+    /// Fails if the evicted message couldn't be written to the log, in which case logging is
+    /// disabled.
+    pub fn push(&mut self, message: PilferMessage, attributes: Attributes) -> io::Result<()> {
+        self.messages
+            .push_back(StoredMessage::new(message, attributes));
+        if self.messages.len() <= self.limit {
+            return Ok(());
+        }
+        let mut evicted = self.messages.pop_front().unwrap();
+        evicted.layout = None;
+        let log = match self.log.as_mut() {
+            Some(log) => log,
+            None => return Ok(()),
+        };
+        if let Err(err) = log.write(&evicted) {
+            self.log = None;
+            self.paged.clear();
+            return Err(err);
+        }
+        // Keep the paged messages contiguous with the in-memory ones, unless there's a gap
+        // between them already
+        if !self.paged.is_empty() && log.paged_from + self.paged.len() + 1 == log.offsets.len() {
+            self.paged.push_back(evicted);
+            if self.paged.len() > self.limit {
+                self.paged.pop_front();
+                log.paged_from += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load up to `count` messages older than the oldest one currently available from the log,
+    /// dropping the newest paged messages if there are more than `limit` of them.
+    pub fn page_in(&mut self, count: usize) -> io::Result<Paged> {
+        let log = match self.log.as_mut() {
+            Some(log) => log,
+            None => return Ok(Paged::default()),
+        };
+        let end = if self.paged.is_empty() {
+            log.offsets.len()
+        } else {
+            log.paged_from
+        };
+        let start = end.saturating_sub(count).max(log.floor);
+        if start >= end {
+            return Ok(Paged::default());
+        }
+        let mut loaded = log.read(start, end - start)?;
+        while let Some(message) = loaded.pop() {
+            self.paged.push_front(message);
+        }
+        log.paged_from = start;
+        let dropped = self.paged.len().saturating_sub(self.limit);
+        self.paged.truncate(self.limit);
+        Ok(Paged {
+            loaded: end - start,
+            dropped,
+        })
+    }
+
+    /// Load up to `count` of the messages between the paged and the in-memory ones, dropping the
+    /// oldest paged messages if there are more than `limit` of them.
+    pub fn page_in_newer(&mut self, count: usize) -> io::Result<Paged> {
+        let log = match self.log.as_mut() {
+            Some(log) if !self.paged.is_empty() => log,
+            _ => return Ok(Paged::default()),
+        };
+        let start = log.paged_from + self.paged.len();
+        let end = (start + count).min(log.offsets.len());
+        if start >= end {
+            return Ok(Paged::default());
+        }
+        self.paged.extend(log.read(start, end - start)?);
+        let dropped = self.paged.len().saturating_sub(self.limit);
+        self.paged.drain(..dropped);
+        log.paged_from += dropped;
+        Ok(Paged {
+            loaded: end - start,
+            dropped,
+        })
+    }
+
+    /// Where the gap between the paged and the in-memory messages is if there is one, counted back
+    /// from the newest message.
+    pub fn gap(&self) -> Option<usize> {
+        let log = self.log.as_ref()?;
+        let contiguous = log.paged_from + self.paged.len() == log.offsets.len();
+        if self.paged.is_empty() || contiguous {
+            None
+        } else {
+            Some(self.messages.len())
+        }
+    }
+
+    /// Find the newest in-memory message matching `predicate`.
+    pub fn find_mut(
+        &mut self,
+        predicate: impl Fn(&PilferMessage) -> bool,
+    ) -> Option<&mut StoredMessage> {
+        self.messages
+            .iter_mut()
+            .rev()
+            .find(|message| predicate(&message.message))
+    }
+
+    /// Remove the newest in-memory message matching `predicate`, returning it along with where it
+    /// was counted back from the newest message.
+    pub fn remove(
+        &mut self,
+        predicate: impl Fn(&PilferMessage) -> bool,
+    ) -> Option<(usize, PilferMessage)> {
+        let index = self
+            .messages
+            .iter()
+            .rposition(|message| predicate(&message.message))?;
+        let message = self.messages.remove(index)?;
+        Some((self.messages.len() - index, message.message))
+    }
+
+    /// Drop the messages paged in from the log.
+    pub fn release_paged(&mut self) {
+        self.paged.clear();
+    }
+
+    /// Remove every message, they won't be paged back in from the log either.
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.paged.clear();
+        if let Some(log) = self.log.as_mut() {
+            log.floor = log.offsets.len();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.paged.len() + self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paged.is_empty() && self.messages.is_empty()
+    }
+
+    /// Iterate over all available messages, oldest first.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &StoredMessage> {
+        self.paged.iter().chain(self.messages.iter())
+    }
+
+    /// Iterate mutably over all available messages, oldest first.
+    pub fn iter_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut StoredMessage> {
+        self.paged.iter_mut().chain(self.messages.iter_mut())
+    }
+}
+
+impl MessageLog {
+    fn write(&mut self, message: &StoredMessage) -> io::Result<()> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.offsets.push(offset);
+        Ok(())
+    }
+
+    fn read(&self, start: usize, count: usize) -> io::Result<Vec<StoredMessage>> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(self.offsets[start]))?;
+        let mut messages = Vec::with_capacity(count);
+        let mut line = String::new();
+        for _ in 0..count {
+            line.clear();
+            reader.read_line(&mut line)?;
+            messages.push(serde_json::from_str(&line)?);
+        }
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Severity, SystemMessage};
+    use std::{env, fs, process};
+
+    fn push(store: &mut MessageStore, number: usize) {
+        let message = SystemMessage::new(Severity::Info, None, number.to_string());
+        store
+            .push(PilferMessage::System(message), Attributes::default())
+            .unwrap();
+    }
+
+    fn contents(store: &MessageStore) -> Vec<usize> {
+        store
+            .iter()
+            .map(|message| message.message().content().parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn pages_through_log() {
+        let path = env::temp_dir().join(format!("pilfer-store-{}.log", process::id()));
+        let mut store = MessageStore::new(3, Some(&path)).unwrap();
+        for number in 0..10 {
+            push(&mut store, number);
+        }
+        assert_eq!(contents(&store), [7, 8, 9]);
+
+        let paged = store.page_in(2).unwrap();
+        assert_eq!(
+            paged,
+            Paged {
+                loaded: 2,
+                dropped: 0
+            }
+        );
+        assert_eq!(contents(&store), [5, 6, 7, 8, 9]);
+        assert_eq!(store.gap(), None);
+
+        // Going past the limit drops the newest paged messages
+        let paged = store.page_in(2).unwrap();
+        assert_eq!(
+            paged,
+            Paged {
+                loaded: 2,
+                dropped: 1
+            }
+        );
+        assert_eq!(contents(&store), [3, 4, 5, 7, 8, 9]);
+        assert_eq!(store.gap(), Some(3));
+
+        // Evicted messages go into the gap
+        push(&mut store, 10);
+        assert_eq!(contents(&store), [3, 4, 5, 8, 9, 10]);
+
+        let paged = store.page_in_newer(5).unwrap();
+        assert_eq!(
+            paged,
+            Paged {
+                loaded: 2,
+                dropped: 2
+            }
+        );
+        assert_eq!(contents(&store), [5, 6, 7, 8, 9, 10]);
+        assert_eq!(store.gap(), None);
+
+        // Without a gap they stay contiguous with the in-memory ones
+        push(&mut store, 11);
+        assert_eq!(contents(&store), [6, 7, 8, 9, 10, 11]);
+
+        store.release_paged();
+        assert_eq!(contents(&store), [9, 10, 11]);
+        let paged = store.page_in(10).unwrap();
+        assert_eq!(
+            paged,
+            Paged {
+                loaded: 9,
+                dropped: 6
+            }
+        );
+        assert_eq!(contents(&store), [0, 1, 2, 9, 10, 11]);
+
+        fs::remove_file(path).unwrap();
+    }
+}
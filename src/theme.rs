@@ -0,0 +1,99 @@
+use tui::style::{Color, Modifier, Style};
+
+use crate::models::{Attributes, OutgoingState, PilferMessage, Severity};
+
+/// The styles messages are rendered with, picked by what they mean
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub name: &'static str,
+    /// Messages from other users
+    pub normal: Style,
+    /// Messages sent by us
+    pub own: Style,
+    /// Messages mentioning us
+    pub mention: Style,
+    /// Messages matching one of the highlight rules
+    pub highlight: Style,
+    /// Our messages which didn't make it to Oprish yet
+    pub pending: Style,
+    /// Applied on top of the selected message's style
+    pub selected: Style,
+    pub info: Style,
+    pub warn: Style,
+    pub error: Style,
+}
+
+impl Theme {
+    /// The names of every built-in theme
+    pub const NAMES: [&'static str; 2] = ["default", "monochrome"];
+
+    pub fn by_name(name: &str) -> Option<Theme> {
+        match name.to_lowercase().as_str() {
+            "default" => Some(Theme::colored()),
+            "monochrome" => Some(Theme::monochrome()),
+            _ => None,
+        }
+    }
+
+    fn colored() -> Theme {
+        Theme {
+            name: "default",
+            normal: Style::default(),
+            own: Style::default(),
+            mention: Style::default().fg(Color::Yellow),
+            highlight: Style::default().fg(Color::Cyan),
+            pending: Style::default().fg(Color::DarkGray),
+            selected: Style::default().bg(Color::DarkGray),
+            info: Style::default().fg(Color::Green),
+            warn: Style::default().fg(Color::Yellow),
+            error: Style::default().fg(Color::Red),
+        }
+    }
+
+    /// For terminals without colours, or people who don't like them
+    fn monochrome() -> Theme {
+        Theme {
+            name: "monochrome",
+            normal: Style::default(),
+            own: Style::default().add_modifier(Modifier::ITALIC),
+            mention: Style::default().add_modifier(Modifier::BOLD),
+            highlight: Style::default().add_modifier(Modifier::UNDERLINED),
+            pending: Style::default().add_modifier(Modifier::DIM),
+            selected: Style::default().add_modifier(Modifier::REVERSED),
+            info: Style::default(),
+            warn: Style::default().add_modifier(Modifier::BOLD),
+            error: Style::default().add_modifier(Modifier::REVERSED),
+        }
+    }
+
+    pub fn severity(&self, severity: Severity) -> Style {
+        match severity {
+            Severity::Info => self.info,
+            Severity::Warn => self.warn,
+            Severity::Error => self.error,
+        }
+    }
+
+    /// The style a message is rendered with.
+    pub fn message(&self, message: &PilferMessage, attributes: Attributes) -> Style {
+        match message {
+            PilferMessage::System(msg) => self.severity(msg.severity),
+            PilferMessage::Outgoing(msg) => match msg.state {
+                OutgoingState::Queued | OutgoingState::Sending => self.pending,
+                OutgoingState::Sent | OutgoingState::Delivered => self.own,
+                OutgoingState::Unconfirmed => self.warn,
+                OutgoingState::Failed(_) => self.error,
+            },
+            PilferMessage::Eludris(_) if attributes.mention => self.mention,
+            PilferMessage::Eludris(_) if attributes.highlight => self.highlight,
+            PilferMessage::Eludris(_) if attributes.own => self.own,
+            PilferMessage::Eludris(_) => self.normal,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::colored()
+    }
+}
@@ -1,120 +1,630 @@
-use crate::AppContext;
+use std::{
+    io::{self, Write},
+    mem,
+    time::Instant,
+};
+
+use crate::{
+    buffer::Tab,
+    info::describe_info,
+    links::find_urls,
+    models::{AppContext, GatewayStats, Popup},
+    preview::Placement,
+};
+use crossterm::{
+    cursor::{RestorePosition, SavePosition},
+    queue,
+};
+use todel::models::InstanceInfo;
 use tui::{
     backend::Backend,
-    layout::{Constraint, Corner, Direction, Layout},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    buffer::{Buffer as TuiBuffer, Cell},
+    layout::{Constraint, Corner, Direction, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Tabs, Widget, Wrap},
     Frame,
 };
-use unicode_width::UnicodeWidthStr;
-
-pub fn ui<B: Backend>(f: &mut Frame<B>, app: &AppContext) {
-    let input_text: Vec<String> = app
-        .input
-        .split('\n') // handles empty line at the end
-        .flat_map(|l| {
-            if l.is_empty() {
-                vec![String::new()]
-            } else {
-                let mut parts: Vec<String> = Vec::new();
-                let mut l = l.to_string();
-                let max_width = f.size().width - 2;
-                loop {
-                    if l.is_empty() {
-                        break;
-                    }
-                    parts.push(
-                        l.drain(
-                            ..if l.len() > max_width as usize {
-                                max_width as usize
-                            } else {
-                                l.len()
-                            },
-                        )
-                        .collect::<String>(),
-                    );
-                }
-                parts.into_iter().collect::<Vec<String>>()
-            }
-        })
-        .rev()
-        .take(((f.size().height - 2) / 3) as usize)
-        .collect::<Vec<String>>()
-        .into_iter()
-        .rev()
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+pub fn ui<B: Backend>(f: &mut Frame<B>, app: &mut AppContext) {
+    let input_lines = input_lines(app.input.text(), f.size().width.saturating_sub(2) as usize);
+    let cursor_line = input_lines
+        .iter()
+        .rposition(|(start, _)| *start <= app.input.cursor())
+        .unwrap_or(0);
+    // Only the last lines of long inputs fit, but the cursor's line is always shown
+    let max_lines = ((f.size().height - 2) / 3) as usize;
+    let input_scroll = input_lines.len().saturating_sub(max_lines).min(cursor_line);
+    let input_text: Vec<&str> = input_lines
+        .iter()
+        .skip(input_scroll)
+        .take(max_lines)
+        .map(|(_, line)| *line)
         .collect();
+    let input_text = input_text.join("\n");
+    let (start, _) = input_lines[cursor_line];
+    let cursor_x = app.input.text()[start..app.input.cursor()].width() as u16;
+    let input_height = input_lines
+        .len()
+        .saturating_sub(input_scroll)
+        .min(max_lines);
 
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints(
             [
+                Constraint::Length(1),
                 Constraint::Min(1),
-                Constraint::Length(
-                    input_text.len() as u16 + if input_text.is_empty() { 3 } else { 2 },
-                ),
+                Constraint::Length(app.notice.is_some() as u16),
+                Constraint::Length(input_height as u16 + if input_height == 0 { 3 } else { 2 }),
             ]
             .as_ref(),
         )
         .split(f.size());
 
-    let messages: Vec<ListItem> = app
+    f.render_widget(tab_bar(app), chunks[0]);
+
+    let mut title = vec![];
+    match app.tab {
+        Tab::Instance(index) => {
+            let instance = &app.instances[index];
+            title.push(Span::raw("Messages"));
+            if app.instances.len() > 1 {
+                title.push(Span::raw(format!(" - {}", instance.info.instance_name)));
+            }
+            if let Some(remaining) = instance
+                .gateway
+                .rate_limited_until
+                .and_then(|until| until.checked_duration_since(Instant::now()))
+            {
+                title.push(Span::styled(
+                    format!(
+                        " Gateway rate limited, {}s left",
+                        remaining.as_secs_f32().ceil()
+                    ),
+                    app.theme.error,
+                ));
+            }
+        }
+        Tab::Mentions => title.push(Span::raw("Mentions")),
+        Tab::Search => match &app.search_query {
+            Some(query) => title.push(Span::raw(format!("Search results for \"{}\"", query))),
+            None => title.push(Span::raw("Search (/search <text> to search)")),
+        },
+        Tab::System => title.push(Span::raw("System")),
+    }
+
+    // Only lay out the messages which actually fit in the view, newest first
+    let width = chunks[1].width.saturating_sub(2);
+    let height = chunks[1].height.saturating_sub(2) as usize;
+    let theme = app.theme;
+    let hyperlinks = app.hyperlinks;
+    let selecting = app.selecting;
+    // Images drawn out of band would cover the popups
+    let place_previews = app.popup.is_none() && !app.show_debug;
+    let (buffer, previews) = app.buffer_and_previews(app.tab);
+    let selected = buffer.selected;
+    if buffer.scroll > 0 {
+        title.push(Span::raw(format!(" (scrolled up {})", buffer.scroll)));
+    }
+    let mut messages: Vec<ListItem> = Vec::with_capacity(height);
+    let mut links = vec![];
+    let mut placements = vec![];
+    let mut rows = Vec::with_capacity(height);
+    for (index, m) in buffer
         .messages
-        .lock()
-        .unwrap()
-        .iter()
-        .flat_map(|m| {
-            // Seperates lines which are longer than the view width with newline characters
-            // since it doesn't wrap sometimes for some reason
-            m.0.to_string()
-                .lines()
-                .flat_map(|l| {
-                    // Probably a newline
-                    if l.is_empty() {
-                        vec![ListItem::new("\n")]
-                    } else {
-                        let mut parts: Vec<String> = Vec::new();
-                        let mut l = l.to_string();
-                        loop {
-                            if l.is_empty() {
-                                break;
-                            }
-                            parts.push(
-                                l.drain(
-                                    ..if l.len() > (chunks[0].width - 2) as usize {
-                                        (chunks[0].width - 2) as usize
-                                    } else {
-                                        l.len()
-                                    },
-                                )
-                                .collect::<String>(),
-                            );
-                        }
-                        parts
-                            .into_iter()
-                            .map(|l| ListItem::new(l).style(m.1))
-                            .collect::<Vec<ListItem>>()
-                    }
-                })
-                .collect::<Vec<ListItem>>()
-        })
+        .iter_mut()
         .rev()
-        .collect();
+        .enumerate()
+        .skip(buffer.scroll)
+    {
+        // Styled here rather than when received so changing the theme restyles history
+        let mut style = theme.message(m.message(), m.attributes);
+        if selected == Some(index) {
+            style = style.patch(theme.selected);
+        }
+        let lines = m.lines(width);
+        // The first previewable link gets its image shown below the message
+        if let Some((url, preview)) = lines
+            .iter()
+            .flat_map(|line| &line.links)
+            .find_map(|link| previews.get(&link.url).map(|preview| (&link.url, preview)))
+        {
+            let top = messages.len() + preview.rows as usize;
+            // Kitty and sixel images can't be cut off
+            if place_previews && top <= height {
+                placements.push(Placement {
+                    url: url.clone(),
+                    x: chunks[1].x + 1,
+                    y: chunks[1].bottom() - 1 - top as u16,
+                });
+            }
+            for line in preview.lines().into_iter().rev() {
+                if messages.len() == height {
+                    break;
+                }
+                messages.push(ListItem::new(line));
+            }
+        }
+        for line in lines.iter().rev() {
+            if messages.len() == height {
+                break;
+            }
+            // The list is drawn bottom up from the inside of its border
+            let y = chunks[1].bottom() - 2 - messages.len() as u16;
+            links.extend(line.links.iter().map(|link| {
+                let link = LineLink {
+                    url: link.url.clone(),
+                    ..*link
+                };
+                (chunks[1].x + 1, y, link)
+            }));
+            messages.push(ListItem::new(line.text.as_str()).style(style));
+        }
+        rows.resize(messages.len(), index);
+        if messages.len() == height {
+            break;
+        }
+    }
 
+    if selecting {
+        title.push(Span::raw(
+            " Selecting: c copy, a copy author, y copy all, r reply, Esc leave",
+        ));
+    }
+    let title = Spans::from(title);
     let message_list = List::new(messages)
-        .block(Block::default().borders(Borders::ALL).title("Messages"))
+        .block(Block::default().borders(Borders::ALL).title(title))
         .start_corner(Corner::BottomLeft);
-    f.render_widget(message_list, chunks[0]);
+    f.render_widget(message_list, chunks[1]);
+    app.previews.placements = placements;
+    app.screen.hyperlinks.clear();
+    if hyperlinks && !links.is_empty() {
+        f.render_widget(
+            Hyperlinks {
+                links: &links,
+                cells: &mut app.screen.hyperlinks,
+            },
+            chunks[1],
+        );
+    }
+    app.screen.messages = chunks[1];
+    app.screen.rows = rows;
+    app.screen.links = links;
+
+    if let Some(notice) = &app.notice {
+        let style = app.theme.severity(notice.severity);
+        // Only the first nine tabs have a number to switch to them with
+        let system_tab = app.tabs().len();
+        let hint = if system_tab <= 9 {
+            format!(" (Alt+{} for the system log)", system_tab)
+        } else {
+            " (Alt+Left/Right to the system log)".to_string()
+        };
+        let line = Spans::from(vec![
+            Span::styled(format!("[{}] {}", notice.severity, notice.content), style),
+            Span::styled(hint, Style::default().fg(Color::DarkGray)),
+        ]);
+        f.render_widget(Paragraph::new(line), chunks[2]);
+    }
+
+    let instance = &app.instances[app.current];
+    let too_long = app.input.len() > instance.info.message_limit;
+    let limit_style = if too_long {
+        app.theme.error
+    } else {
+        Style::default()
+    };
+    let mut title = vec![
+        Span::raw("Input "),
+        Span::styled(
+            format!("{}/{}", app.input.len(), instance.info.message_limit),
+            limit_style,
+        ),
+    ];
+    if too_long {
+        title.push(Span::styled(
+            format!(
+                " Too long, Ctrl+S to split into {} messages",
+                app.input.parts(instance.info.message_limit)
+            ),
+            limit_style,
+        ));
+    }
+    // Make it clear where messages go when not looking at an instance's chat
+    if app.instances.len() > 1 && app.tab != Tab::Instance(app.current) {
+        title.push(Span::raw(format!(
+            " (sending to {})",
+            instance.info.instance_name
+        )));
+    }
+    if !instance.outbox.is_empty() {
+        title.push(Span::raw(format!(" ({} queued)", instance.outbox.len())));
+        if !instance.gateway.connected {
+            title.push(Span::styled(
+                " Offline, sending once reconnected (Ctrl+D to discard)",
+                app.theme.warn,
+            ));
+        }
+    }
+    if let Some(upload) = &instance.upload {
+        title.push(Span::raw(format!(
+            " Uploading {} {}%",
+            upload.name,
+            upload.sent * 100 / upload.total.max(1)
+        )));
+    }
+    if let Some(remaining) = instance
+        .rate_limiter
+        .wait_until(Instant::now())
+        .and_then(|until| until.checked_duration_since(Instant::now()))
+    {
+        title.push(Span::styled(
+            format!(" Rate limited, {}s left", remaining.as_secs_f32().ceil()),
+            app.theme.error,
+        ));
+    }
 
-    let text = input_text.join("\n");
-    let input =
-        Paragraph::new(text.as_ref()).block(Block::default().borders(Borders::ALL).title("Input"));
-    f.render_widget(input, chunks[1]);
+    let input = Paragraph::new(input_text).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(Spans::from(title)),
+    );
+    f.render_widget(input, chunks[3]);
     f.set_cursor(
-        chunks[1].x + input_text.last().map(|l| l.width()).unwrap_or(0) as u16 + 1,
-        chunks[1].y
-            + if input_text.is_empty() {
-                1
+        chunks[3].x + cursor_x + 1,
+        chunks[3].y + cursor_line.saturating_sub(input_scroll) as u16 + 1,
+    );
+    app.screen.input = chunks[3];
+    app.screen.input_scroll = input_scroll;
+
+    match app.popup.as_mut() {
+        Some(Popup::Info { scroll }) => info_popup(f, &instance.info, scroll),
+        Some(Popup::Links { urls, selected }) => link_picker(f, urls, *selected),
+        None => {}
+    }
+
+    if app.show_debug {
+        debug_view(f, &instance.gateway);
+    }
+}
+
+/// One tab per buffer with its unread and mention counts, highlighting those with new activity
+fn tab_bar(app: &AppContext) -> Tabs<'static> {
+    let tabs = app.tabs();
+    let titles = tabs
+        .iter()
+        .enumerate()
+        .map(|(i, tab)| {
+            let name = match tab {
+                Tab::Instance(index) => app.instances[*index].info.instance_name.as_str(),
+                Tab::Mentions => "Mentions",
+                Tab::Search => "Search",
+                Tab::System => "System",
+            };
+            let buffer = app.buffer(*tab);
+            let style = if buffer.mentions > 0 {
+                app.theme.mention.add_modifier(Modifier::BOLD)
+            } else if buffer.unread > 0 {
+                Style::default().add_modifier(Modifier::BOLD)
             } else {
-                input_text.len() as u16
-            },
+                Style::default()
+            };
+            let mut title = format!("{} {}", i + 1, name);
+            if buffer.unread > 0 {
+                title.push_str(&format!(" ({})", buffer.unread));
+            }
+            if buffer.mentions > 0 {
+                title.push_str(&format!(" @{}", buffer.mentions));
+            }
+            Spans::from(Span::styled(title, style))
+        })
+        .collect();
+    Tabs::new(titles)
+        .select(tabs.iter().position(|tab| *tab == app.tab).unwrap_or(0))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+}
+
+/// Scrollable popup with everything the instance reports about itself
+fn info_popup<B: Backend>(f: &mut Frame<B>, info: &InstanceInfo, scroll: &mut u16) {
+    let text: Vec<Spans> = describe_info(info).into_iter().map(Spans::from).collect();
+    let area = centered_rect(80, text.len() as u16 + 2, f.size());
+    // Don't scroll past the last line
+    *scroll = (*scroll).min((text.len() as u16).saturating_sub(area.height.saturating_sub(2)));
+    let popup = Paragraph::new(text)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("{} (Esc to close)", info.instance_name)),
+        )
+        .wrap(Wrap { trim: false })
+        .scroll((*scroll, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Numbered list of URLs to open or copy
+fn link_picker<B: Backend>(f: &mut Frame<B>, urls: &[String], selected: usize) {
+    let area = centered_rect(80, urls.len() as u16 + 2, f.size());
+    let items: Vec<ListItem> = urls
+        .iter()
+        .enumerate()
+        .map(|(i, url)| {
+            let item = ListItem::new(format!("{:>2} {}", i + 1, url));
+            if i == selected {
+                item.style(Style::default().add_modifier(Modifier::REVERSED))
+            } else {
+                item
+            }
+        })
+        // Keep the selected URL in view
+        .skip((selected + 1).saturating_sub(area.height.saturating_sub(2) as usize))
+        .collect();
+    let popup = List::new(items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("Links (Enter to open, c to copy, Esc to close)"),
     );
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// Popup with the gateway connection's statistics
+fn debug_view<B: Backend>(f: &mut Frame<B>, stats: &GatewayStats) {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let text = vec![
+        Spans::from(format!(
+            "State: {}",
+            if stats.connected {
+                "connected"
+            } else {
+                "disconnected"
+            }
+        )),
+        Spans::from(format!(
+            "Latency: {}",
+            optional(stats.latency.map(|l| format!("{}ms", l.as_millis())))
+        )),
+        Spans::from(format!("Pongs: {}", stats.pongs)),
+        Spans::from(format!("Rate limits: {}", stats.rate_limits)),
+        Spans::from(format!("Reconnects: {}", stats.reconnects)),
+        Spans::from(format!("Unknown frames: {}", stats.unknown_frames)),
+        Spans::from(format!("Undecodable frames: {}", stats.undecodable_frames)),
+        Spans::from(format!(
+            "Last error: {}",
+            optional(stats.last_error.clone())
+        )),
+    ];
+    let area = centered_rect(60, text.len() as u16 + 2, f.size());
+    let popup = Paragraph::new(text)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Gateway (F12)"),
+        )
+        .wrap(Wrap { trim: false });
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// A rect `percent_x` percent wide and `height` rows tall, centered in `area`
+fn centered_rect(percent_x: u16, height: u16, area: Rect) -> Rect {
+    let width = area.width * percent_x / 100;
+    let height = height.min(area.height);
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// Where things ended up on the screen in the last frame, to map mouse clicks back to them
+#[derive(Debug, Default)]
+pub struct ScreenLayout {
+    pub messages: Rect,
+    /// The message shown on every row of the message list from the bottom up, counted back from
+    /// the newest message
+    pub rows: Vec<usize>,
+    /// Every link in the message list along with where its line starts
+    pub links: Vec<(u16, u16, LineLink)>,
+    /// The cells of those links to draw with escape sequences on top of the frame
+    pub hyperlinks: Vec<(u16, u16, Cell)>,
+    pub input: Rect,
+    /// How many lines of the input are scrolled out of view at the top
+    pub input_scroll: usize,
+}
+
+/// Wrap the input to the given display width, returning every line along with the byte offset it
+/// starts at.
+pub fn input_lines(input: &str, width: usize) -> Vec<(usize, &str)> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    for line in input.split('\n') {
+        let mut start = 0;
+        let mut current_width = 0;
+        for (index, c) in line.char_indices() {
+            let char_width = c.width().unwrap_or(0);
+            if current_width + char_width > width && index > start {
+                lines.push((offset + start, &line[start..index]));
+                start = index;
+                current_width = 0;
+            }
+            current_width += char_width;
+        }
+        lines.push((offset + start, &line[start..]));
+        offset += line.len() + 1;
+    }
+    lines
+}
+
+/// The byte offset of the character shown at `column` in `line`, or its end if it's shorter.
+pub fn column_offset(line: &str, column: usize) -> usize {
+    let mut width = 0;
+    for (index, c) in line.char_indices() {
+        width += c.width().unwrap_or(0);
+        if width > column {
+            return index;
+        }
+    }
+    line.len()
+}
+
+/// A wrapped line of a message
+#[derive(Debug, Default)]
+pub struct Line {
+    pub text: String,
+    /// The URLs in this line, by the columns they cover
+    pub links: Vec<LineLink>,
+}
+
+#[derive(Debug)]
+pub struct LineLink {
+    pub column: usize,
+    pub width: usize,
+    pub url: String,
+}
+
+/// Hard wrap text to the given display width.
+///
+/// URLs which would be split get moved to a line of their own if they fit on one.
+pub fn wrap(text: &str, width: usize) -> Vec<Line> {
+    let mut lines = vec![];
+    for line in text.lines() {
+        let urls = find_urls(line);
+        let mut current = Line::default();
+        let mut current_width = 0;
+        for (index, c) in line.char_indices() {
+            let char_width = c.width().unwrap_or(0);
+            let url_width = urls
+                .iter()
+                .find(|url| url.start == index)
+                .map(|url| line[url.clone()].width());
+            let overflows = match url_width {
+                Some(url_width) if url_width <= width => current_width + url_width > width,
+                _ => current_width + char_width > width,
+            };
+            if overflows && !current.text.is_empty() {
+                lines.push(mem::take(&mut current));
+                current_width = 0;
+            }
+            if let Some(url) = urls.iter().find(|url| url.contains(&index)) {
+                let url = &line[url.clone()];
+                match current.links.last_mut() {
+                    Some(link) if link.url == url && link.column + link.width == current_width => {
+                        link.width += char_width;
+                    }
+                    _ => current.links.push(LineLink {
+                        column: current_width,
+                        width: char_width,
+                        url: url.to_string(),
+                    }),
+                }
+            }
+            current.text.push(c);
+            current_width += char_width;
+        }
+        lines.push(current);
+    }
+    lines
+}
+
+/// Copies the cells links were drawn to, wrapped in OSC 8 escape sequences so terminals
+/// supporting them make the links clickable.
+///
+/// The sequences can't stay in the frame as tui counts them towards the width of the cells, so
+/// the copies get drawn over it by [`draw_hyperlinks`] once it's flushed.
+struct Hyperlinks<'a> {
+    links: &'a [(u16, u16, LineLink)],
+    cells: &'a mut Vec<(u16, u16, Cell)>,
+}
+
+impl Widget for Hyperlinks<'_> {
+    fn render(self, area: Rect, buf: &mut TuiBuffer) {
+        *self.cells = hyperlink_cells(buf, area, self.links);
+    }
+}
+
+fn hyperlink_cells(
+    buf: &TuiBuffer,
+    area: Rect,
+    links: &[(u16, u16, LineLink)],
+) -> Vec<(u16, u16, Cell)> {
+    let mut cells = vec![];
+    for (x, y, link) in links {
+        let (x, y) = (*x + link.column as u16, *y);
+        if link.width == 0 || x + link.width as u16 > area.right() {
+            continue;
+        }
+        let start = cells.len();
+        // The cells covered by wide characters are left alone like tui does
+        let mut skip = 0;
+        for x in x..x + link.width as u16 {
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            let cell = buf.get(x, y).clone();
+            skip = cell.symbol.width().saturating_sub(1);
+            cells.push((x, y, cell));
+        }
+        if let Some((_, _, first)) = cells.get_mut(start) {
+            first.symbol = format!("\x1b]8;;{}\x1b\\{}", link.url, first.symbol);
+        }
+        if let Some((_, _, last)) = cells.last_mut() {
+            last.symbol.push_str("\x1b]8;;\x1b\\");
+        }
+    }
+    cells
+}
+
+/// Draw the hyperlinks of the last frame over it, leaving the cursor where it was.
+pub fn draw_hyperlinks<B: Backend + Write>(
+    backend: &mut B,
+    cells: &[(u16, u16, Cell)],
+) -> io::Result<()> {
+    if cells.is_empty() {
+        return Ok(());
+    }
+    queue!(backend, SavePosition)?;
+    backend.draw(cells.iter().map(|(x, y, cell)| (*x, *y, cell)))?;
+    queue!(backend, RestorePosition)?;
+    Backend::flush(backend)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hyperlinks_leave_frame_intact() {
+        let text = "see https://eludris.gay/\u{1f980} now";
+        let area = Rect::new(0, 0, text.width() as u16, 1);
+        let line = wrap(text, 40).remove(0);
+        let links: Vec<_> = line.links.into_iter().map(|link| (0, 0, link)).collect();
+        let mut blank = Cell::default();
+        blank.set_symbol("#");
+        let previous = TuiBuffer::filled(area, &blank);
+        let mut buf = TuiBuffer::empty(area);
+        buf.set_string(0, 0, text, Style::default());
+        let cells = hyperlink_cells(&buf, area, &links);
+
+        // Every column but the one covered by the wide character still gets drawn
+        let updates = previous.diff(&buf);
+        assert_eq!(updates.len(), text.width() - 1);
+        assert!(updates
+            .iter()
+            .all(|(_, _, cell)| !cell.symbol.contains('\x1b')));
+
+        assert_eq!(cells.len(), "https://eludris.gay/\u{1f980}".chars().count());
+        assert_eq!(cells[0].0, 4);
+        assert_eq!(
+            cells[0].2.symbol,
+            "\x1b]8;;https://eludris.gay/\u{1f980}\x1b\\h"
+        );
+        let (x, _, last) = cells.last().unwrap();
+        assert_eq!(*x, 24);
+        assert_eq!(last.symbol, "\u{1f980}\x1b]8;;\x1b\\");
+    }
 }
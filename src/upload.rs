@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+
+use futures::TryStreamExt;
+use reqwest::{
+    multipart::{Form, Part},
+    Body, Client,
+};
+use todel::models::FileData;
+use tokio::{fs::File, sync::mpsc::UnboundedSender};
+use tokio_util::io::ReaderStream;
+
+use crate::models::{AppEvent, FileResponse, UploadEvent};
+
+/// Upload a file to Effis, reporting its progress along the way.
+pub async fn upload_file(
+    http_client: Client,
+    effis_url: String,
+    path: PathBuf,
+    size: u64,
+    instance: usize,
+    events: UnboundedSender<(usize, AppEvent)>,
+) {
+    let result = send_file(&http_client, &effis_url, path, size, instance, &events).await;
+    events
+        .send((instance, AppEvent::Upload(UploadEvent::Done(result))))
+        .ok();
+}
+
+async fn send_file(
+    http_client: &Client,
+    effis_url: &str,
+    path: PathBuf,
+    size: u64,
+    instance: usize,
+    events: &UnboundedSender<(usize, AppEvent)>,
+) -> Result<FileData, String> {
+    let file = File::open(&path).await.map_err(|err| err.to_string())?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let progress = events.clone();
+    let mut sent = 0;
+    let mut percent = 0;
+    let stream = ReaderStream::new(file).inspect_ok(move |chunk| {
+        sent += chunk.len() as u64;
+        // Only report whole percents so big files don't flood the UI with redraws
+        let current = sent * 100 / size.max(1);
+        if current != percent {
+            percent = current;
+            progress
+                .send((instance, AppEvent::Upload(UploadEvent::Progress(sent))))
+                .ok();
+        }
+    });
+    let part = Part::stream_with_length(Body::wrap_stream(stream), size).file_name(name);
+
+    let res = http_client
+        .post(format!("{}/", effis_url.trim_end_matches('/')))
+        .multipart(Form::new().part("file", part))
+        .send()
+        .await
+        .map_err(|err| format!("{:?}", err))?;
+    match res.json::<FileResponse>().await {
+        Ok(FileResponse::Success(file)) => Ok(file),
+        Ok(FileResponse::Error(err)) => Err(format!("{:?}", err)),
+        Err(_) => Err("got invalid response".to_string()),
+    }
+}
+
+/// The link a file uploaded to Effis can be viewed at.
+pub fn file_url(effis_url: &str, file: &FileData) -> String {
+    format!("{}/{}", effis_url.trim_end_matches('/'), file.id)
+}